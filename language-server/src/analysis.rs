@@ -1,10 +1,16 @@
-use std::{collections::HashMap, iter};
+use std::collections::HashMap;
 
 use pest::iterators::Pairs;
-use pest_meta::parser::Rule;
+use pest_meta::{
+    ast::{Rule as AstRule, RuleType},
+    parser::Rule,
+};
 use tower_lsp::lsp_types::Range;
 
-use crate::helpers::{FindReferences, IntoRange};
+use crate::{
+    helpers::{FindReferences, IntoRange, RangeContains},
+    line_index::LineIndex,
+};
 
 #[derive(Debug, Clone)]
 /// Stores analysis information for a rule.
@@ -13,6 +19,8 @@ pub struct RuleAnalysis {
     pub definition_location: Range,
     /// The location of the name definition of the rule.
     pub identifier_location: Range,
+    /// The type of the rule, as set by its modifier.
+    pub rule_type: RuleType,
     /// The tokens that make up the rule.
     pub tokens: Vec<(String, Range)>,
     /// The rules expression, in [String] form.
@@ -22,6 +30,8 @@ pub struct RuleAnalysis {
     pub references: Vec<Range>,
     /// The rules documentation, in markdown.
     pub doc: Option<String>,
+    /// The location of the `///` comments documenting the rule, if any.
+    pub doc_location: Option<Range>,
 }
 
 #[derive(Debug)]
@@ -30,11 +40,23 @@ pub struct Analysis {
     /// Holds analyses for individual rules.
     /// [RuleAnalysis] is [None] for builtins.
     pub rules: HashMap<String, RuleAnalysis>,
+    /// The validated rules of the grammar, as returned by [pest_meta::parser::consume_rules].
+    /// [None] if the grammar has not been validated.
+    pub ast: Option<Vec<AstRule>>,
+    /// The occurrences of every identifier in the document, other than rule definitions, by
+    /// name. Unlike [RuleAnalysis::references], this includes rules defined in other documents.
+    pub identifiers: HashMap<String, Vec<Range>>,
+    /// The grammar's documentation, from its `//!` comments, in markdown.
+    pub doc: Option<String>,
+    /// The location of the `//!` comments, if any.
+    pub doc_location: Option<Range>,
 }
 
 impl Analysis {
-    pub fn new(pairs: Pairs<Rule>, capacity: Option<usize>) -> Self {
+    /// Analyses the `pairs` of a grammar, with positions from an `index` of its text.
+    pub fn new(pairs: Pairs<Rule>, index: &LineIndex, capacity: Option<usize>) -> Self {
         let mut precending_docs: Option<String> = None;
+        let mut precending_docs_location: Option<Range> = None;
         let mut rules = match capacity {
             Some(capacity) => HashMap::with_capacity(capacity),
             None => HashMap::new(),
@@ -52,41 +74,58 @@ impl Analysis {
                 (Rule::line_doc, Some(docs)) => {
                     docs.push_str(inner.into_inner().next().unwrap().as_str());
                     docs.push('\n');
+                    if let Some(location) = &mut precending_docs_location {
+                        location.end = current_span.into_range(index).end;
+                    }
                 }
 
                 (Rule::line_doc, _) => {
                     let mut docs = inner.into_inner().next().unwrap().as_str().to_string();
                     docs.push('\n');
                     precending_docs = Some(docs);
+                    precending_docs_location = Some(current_span.into_range(index));
                 }
 
                 (Rule::identifier, _) => {
+                    let doc_location = precending_docs_location.take();
                     let mut doc = precending_docs.take();
                     if let Some(doc) = &mut doc {
                         doc.pop();
                     }
 
+                    let rule_type = inner_pairs
+                        .clone()
+                        .find_map(|pair| match pair.as_rule() {
+                            Rule::silent_modifier => Some(RuleType::Silent),
+                            Rule::atomic_modifier => Some(RuleType::Atomic),
+                            Rule::compound_atomic_modifier => Some(RuleType::CompoundAtomic),
+                            Rule::non_atomic_modifier => Some(RuleType::NonAtomic),
+                            _ => None,
+                        })
+                        .unwrap_or(RuleType::Normal);
                     let expression_pair = inner_pairs
                         .find(|r| r.as_rule() == Rule::expression)
                         .expect("rule should contain expression");
                     let expression = expression_pair.as_str().to_owned();
-                    let expression_range = expression_pair.as_span().into_range();
+                    let expression_range = expression_pair.as_span().into_range(index);
                     let tokens = expression_pair
                         .into_inner()
-                        .map(|e| (e.as_str().to_owned(), e.as_span().into_range()))
+                        .map(|e| (e.as_str().to_owned(), e.as_span().into_range(index)))
                         .collect();
-                    let references = pairs.clone().find_references(inner.as_span());
+                    let references = pairs.clone().find_references(inner.as_span(), index);
 
-                    let definition_location = current_span.into_range();
-                    let identifier_location = inner.as_span().into_range();
+                    let definition_location = current_span.into_range(index);
+                    let identifier_location = inner.as_span().into_range(index);
                     let analisys = RuleAnalysis {
                         identifier_location,
+                        rule_type,
                         definition_location,
                         tokens,
                         expression,
                         expression_range,
                         references,
                         doc,
+                        doc_location,
                     };
                     rules.insert(inner.as_str().to_owned(), analisys);
                 }
@@ -94,12 +133,78 @@ impl Analysis {
             }
         }
 
-        Analysis { rules }
+        let mut doc: Option<String> = None;
+        let mut doc_location: Option<Range> = None;
+        for pair in pairs
+            .clone()
+            .filter(|pair| pair.as_rule() == Rule::grammar_doc)
+        {
+            let range = pair.as_span().into_range(index);
+            let line = pair.into_inner().next().unwrap().as_str();
+            match (&mut doc, &mut doc_location) {
+                (Some(doc), Some(location)) => {
+                    doc.push('\n');
+                    doc.push_str(line);
+                    location.end = range.end;
+                }
+                _ => {
+                    doc = Some(line.to_owned());
+                    doc_location = Some(range);
+                }
+            }
+        }
+
+        let mut identifiers: HashMap<String, Vec<Range>> = HashMap::new();
+        for pair in pairs.flatten() {
+            let span = pair.as_span();
+            if pair.as_rule() == Rule::identifier
+                && !rules
+                    .get(pair.as_str())
+                    .is_some_and(|ra| ra.identifier_location == span.into_range(index))
+            {
+                identifiers
+                    .entry(pair.as_str().to_owned())
+                    .or_default()
+                    .push(span.into_range(index));
+            }
+        }
+
+        Analysis {
+            rules,
+            ast: None,
+            identifiers,
+            doc,
+            doc_location,
+        }
+    }
+
+    /// Returns the rule whose definition contains `range`.
+    pub fn rule_containing(&self, range: Range) -> Option<(&str, &RuleAnalysis)> {
+        self.rules
+            .iter()
+            .find(|(_, ra)| ra.definition_location.contains(range))
+            .map(|(name, ra)| (name.as_str(), ra))
+    }
+
+    /// Returns every call in the document, as the name of the calling rule and the identifier
+    /// it calls, which may be a rule of another document or a builtin.
+    pub fn calls(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.identifiers.iter().flat_map(move |(callee, ranges)| {
+            ranges.iter().filter_map(move |range| {
+                let (caller, _) = self.rule_containing(*range)?;
+                Some((caller, callee.as_str()))
+            })
+        })
     }
 
-    pub fn unused_rules(&self) -> impl Iterator<Item = (&str, Range)> {
-        self.rules.iter().filter_map(|(name, ra)| {
-            if ra.references.is_empty()
+    /// Returns the rules that `is_referenced` reports as unused, other than those which are
+    /// always used by pest.
+    pub fn unused_rules<'a>(
+        &'a self,
+        is_referenced: impl Fn(&str) -> bool + 'a,
+    ) -> impl Iterator<Item = (&'a str, Range)> {
+        self.rules.iter().filter_map(move |(name, ra)| {
+            if !is_referenced(name)
                 && !name.starts_with('_')
                 && name != "COMMENT"
                 && name != "WHITE_SPACE"
@@ -111,12 +216,3 @@ impl Analysis {
         })
     }
 }
-
-impl RuleAnalysis {
-    pub fn references_and_identifier(&self) -> impl Iterator<Item = Range> {
-        self.references
-            .iter()
-            .copied()
-            .chain(iter::once(self.identifier_location))
-    }
-}
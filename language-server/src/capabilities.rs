@@ -1,17 +1,26 @@
+use strum::IntoEnumIterator;
 use tower_lsp::lsp_types::{
-    CodeActionKind, CodeActionOptions, CodeActionProviderCapability, CompletionOptions,
-    FileOperationFilter, FileOperationPattern, FileOperationRegistrationOptions,
-    HoverProviderCapability, InitializeResult, OneOf, ServerCapabilities, ServerInfo,
-    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    CallHierarchyServerCapability, CodeActionKind, CodeActionOptions, CodeActionProviderCapability,
+    CompletionOptions, ExecuteCommandOptions, FileOperationFilter, FileOperationPattern,
+    FileOperationRegistrationOptions, HoverProviderCapability, InitializeResult, OneOf,
+    RenameOptions, SaveOptions, SemanticTokensFullOptions, SemanticTokensOptions,
+    SemanticTokensServerCapabilities, ServerCapabilities, ServerInfo, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions,
     WorkspaceFileOperationsServerCapabilities, WorkspaceServerCapabilities,
 };
 
-/// Returns the capabilities of the language server.
-pub fn capabilities() -> InitializeResult {
+use crate::{commands::Command, line_index::PositionEncoding, semantic_tokens};
+
+/// Returns the capabilities of the language server, which uses the negotiated position
+/// `encoding`.
+pub fn capabilities(encoding: PositionEncoding) -> InitializeResult {
     let text_document_sync = Some(TextDocumentSyncCapability::Options(
         TextDocumentSyncOptions {
-            change: Some(TextDocumentSyncKind::FULL),
+            change: Some(TextDocumentSyncKind::INCREMENTAL),
             open_close: Some(true),
+            save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                include_text: Some(false),
+            })),
             ..Default::default()
         },
     ));
@@ -29,6 +38,7 @@ pub fn capabilities() -> InitializeResult {
     });
 
     let code_action_kinds = Some(vec![
+        CodeActionKind::QUICKFIX,
         CodeActionKind::REFACTOR_EXTRACT,
         CodeActionKind::REFACTOR_INLINE,
     ]);
@@ -40,7 +50,7 @@ pub fn capabilities() -> InitializeResult {
 
     let filters = vec![FileOperationFilter {
         pattern: FileOperationPattern {
-            glob: "** /*.pest".to_string(),
+            glob: "**/*.pest".to_string(),
             ..Default::default()
         },
         ..Default::default()
@@ -59,12 +69,29 @@ pub fn capabilities() -> InitializeResult {
         ..Default::default()
     });
 
+    let execute_command_provider = Some(ExecuteCommandOptions {
+        commands: Command::iter()
+            .map(|command| command.as_ref().to_string())
+            .collect(),
+        ..Default::default()
+    });
+
+    let semantic_tokens_provider = Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+        SemanticTokensOptions {
+            legend: semantic_tokens::legend(),
+            range: Some(true),
+            full: Some(SemanticTokensFullOptions::Bool(true)),
+            ..Default::default()
+        },
+    ));
+
     let server_info = Some(ServerInfo {
         name: "Pest Language Server".to_string(),
         version: Some(env!("CARGO_PKG_VERSION").to_string()),
     });
 
     let capabilities = ServerCapabilities {
+        position_encoding: Some(encoding.kind()),
         text_document_sync,
         hover_provider: Some(HoverProviderCapability::Simple(true)),
         completion_provider,
@@ -73,7 +100,15 @@ pub fn capabilities() -> InitializeResult {
         references_provider: Some(OneOf::Left(true)),
         document_symbol_provider: Some(OneOf::Left(true)),
         document_formatting_provider: Some(OneOf::Left(true)),
-        rename_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Right(RenameOptions {
+            prepare_provider: Some(true),
+            work_done_progress_options: Default::default(),
+        })),
+        call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+        execute_command_provider,
+        semantic_tokens_provider,
+        inlay_hint_provider: Some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
         workspace,
         ..Default::default()
     };
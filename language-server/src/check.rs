@@ -0,0 +1,314 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use serde_json::{Value, json};
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Range, TextDocumentItem, Url};
+
+use crate::{
+    line_index::PositionEncoding,
+    lsp::Config,
+    rust::{RustSource, crate_root, rust_files},
+    units::analyse_unit,
+};
+
+/// The diagnostics for a single checked file.
+struct FileDiagnostics {
+    path: PathBuf,
+    uri: Url,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Returns the `check` subcommand, which runs the server's diagnostics without a client.
+pub fn subcommand() -> Command {
+    Command::new("check")
+        .about("Check grammars for errors and warnings, without starting the server")
+        .arg(
+            Arg::new("files")
+                .help("The grammar files to check")
+                .required(true)
+                .num_args(1..)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("format")
+                .help("The output format")
+                .long("format")
+                .value_parser(["text", "json", "sarif"])
+                .default_value("text"),
+        )
+        .arg(
+            Arg::new("always-used")
+                .help("A rule name to exclude from the unused rule warnings")
+                .long("always-used")
+                .value_name("RULE")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("unit")
+                .help("Check the files together, as a single grammar unit")
+                .long("unit")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("deny-warnings")
+                .help("Exit with a failure code if there are any warnings")
+                .long("deny-warnings")
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Runs the `check` subcommand.
+pub fn run(matches: &ArgMatches) -> ExitCode {
+    let config = Config {
+        always_used_rule_names: matches
+            .get_many::<String>("always-used")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+
+    let mut failed = false;
+    let documents: Vec<_> = matches
+        .get_many::<PathBuf>("files")
+        .unwrap_or_default()
+        .filter_map(|path| match read_document(path) {
+            Ok(document) => Some((path.clone(), document)),
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                failed = true;
+                None
+            }
+        })
+        .collect();
+
+    let results = if matches.get_flag("unit") {
+        check_unit(&config, &documents)
+    } else {
+        documents
+            .chunks(1)
+            .flat_map(|document| check_unit(&config, document))
+            .collect()
+    };
+
+    let deny_warnings = matches.get_flag("deny-warnings");
+    failed |= results.iter().flat_map(|file| &file.diagnostics).any(|d| {
+        d.severity == Some(DiagnosticSeverity::ERROR)
+            || (deny_warnings && d.severity == Some(DiagnosticSeverity::WARNING))
+    });
+
+    match matches.get_one::<String>("format").map(String::as_str) {
+        Some("json") => println!("{:#}", json_output(&results)),
+        Some("sarif") => println!("{:#}", sarif_output(&results)),
+        _ => print!("{}", text_output(&results)),
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+pub fn read_document(path: &Path) -> Result<TextDocumentItem, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let absolute = fs::canonicalize(path).map_err(|e| e.to_string())?;
+    let uri = Url::from_file_path(absolute).map_err(|_| "invalid path".to_owned())?;
+    Ok(TextDocumentItem::new(uri, "pest".to_owned(), 0, text))
+}
+
+fn check_unit(config: &Config, documents: &[(PathBuf, TextDocumentItem)]) -> Vec<FileDiagnostics> {
+    let unit: Vec<_> = documents.iter().map(|(_, document)| document).collect();
+    let rust_references = rust_references(&unit);
+
+    // Columns are reported in characters, as by most compilers.
+    analyse_unit(
+        config,
+        &unit,
+        PositionEncoding::Utf32,
+        |_| None,
+        |rule_name| rust_references.contains(rule_name),
+    )
+    .into_iter()
+    .zip(documents)
+    .map(|(result, (path, _))| {
+        let mut diagnostics = result.diagnostics;
+        diagnostics.sort_by_key(|diagnostic| diagnostic.range.start);
+        FileDiagnostics {
+            path: path.clone(),
+            uri: result.uri,
+            diagnostics,
+        }
+    })
+    .collect()
+}
+
+/// Returns the names of the rules referenced as `Rule::name` in the Rust code of the crates the
+/// grammars belong to.
+fn rust_references(unit: &[&TextDocumentItem]) -> HashSet<String> {
+    let crate_roots: HashSet<_> = unit
+        .iter()
+        .filter_map(|document| crate_root(&document.uri.to_file_path().ok()?).map(Path::to_owned))
+        .collect();
+
+    crate_roots
+        .iter()
+        .flat_map(|root| rust_files(root))
+        .filter_map(|path| {
+            let uri = Url::from_file_path(&path).ok()?;
+            let text = fs::read_to_string(&path).ok()?;
+            let source = RustSource::new(&uri, &text, PositionEncoding::Utf32);
+            (source
+                .crate_root
+                .as_ref()
+                .is_some_and(|root| crate_roots.contains(root)))
+            .then_some(source.rule_references.into_keys())
+        })
+        .flatten()
+        .collect()
+}
+
+/// Returns the path of a related location as it was given on the command line, if it is in
+/// one of the checked files.
+fn related_path(results: &[FileDiagnostics], uri: &Url) -> PathBuf {
+    results
+        .iter()
+        .find(|file| file.uri == *uri)
+        .map(|file| file.path.clone())
+        .or_else(|| uri.to_file_path().ok())
+        .unwrap_or_else(|| PathBuf::from(uri.as_str()))
+}
+
+fn severity_name(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "error",
+    }
+}
+
+fn text_output(results: &[FileDiagnostics]) -> String {
+    let mut output = String::new();
+
+    for FileDiagnostics {
+        path, diagnostics, ..
+    } in results
+    {
+        for diagnostic in diagnostics {
+            let start = diagnostic.range.start;
+            output.push_str(&format!(
+                "{}:{}:{}: {}: {}\n",
+                path.display(),
+                start.line + 1,
+                start.character + 1,
+                severity_name(diagnostic),
+                diagnostic.message
+            ));
+
+            for related in diagnostic.related_information.iter().flatten() {
+                let start = related.location.range.start;
+                output.push_str(&format!(
+                    "  note: {}:{}:{}: {}\n",
+                    related_path(results, &related.location.uri).display(),
+                    start.line + 1,
+                    start.character + 1,
+                    related.message
+                ));
+            }
+        }
+    }
+
+    output
+}
+
+fn json_output(results: &[FileDiagnostics]) -> Value {
+    results
+        .iter()
+        .map(
+            |FileDiagnostics {
+                 path, diagnostics, ..
+             }| {
+                json!({
+                    "file": path,
+                    "diagnostics": diagnostics,
+                })
+            },
+        )
+        .collect()
+}
+
+/// Formats the diagnostics as a [SARIF](https://sarifweb.azurewebsites.net/) log, for code
+/// scanning tools.
+fn sarif_output(results: &[FileDiagnostics]) -> Value {
+    fn location(path: &Path, range: Range) -> Value {
+        json!({
+            "physicalLocation": {
+                "artifactLocation": { "uri": path.to_string_lossy().replace('\\', "/") },
+                "region": {
+                    "startLine": range.start.line + 1,
+                    "startColumn": range.start.character + 1,
+                    "endLine": range.end.line + 1,
+                    "endColumn": range.end.character + 1,
+                },
+            },
+        })
+    }
+
+    let sarif_results: Vec<_> = results
+        .iter()
+        .flat_map(
+            |FileDiagnostics {
+                 path, diagnostics, ..
+             }| {
+                diagnostics.iter().map(move |diagnostic| {
+                    let level = match diagnostic.severity {
+                        Some(DiagnosticSeverity::WARNING) => "warning",
+                        Some(DiagnosticSeverity::INFORMATION | DiagnosticSeverity::HINT) => "note",
+                        _ => "error",
+                    };
+                    let related_locations: Vec<_> = diagnostic
+                        .related_information
+                        .iter()
+                        .flatten()
+                        .map(|related| {
+                            let related_path = related_path(results, &related.location.uri);
+                            let mut related_location =
+                                location(&related_path, related.location.range);
+                            related_location["message"] = json!({ "text": related.message });
+                            related_location
+                        })
+                        .collect();
+
+                    json!({
+                        "level": level,
+                        "message": { "text": diagnostic.message },
+                        "locations": [location(path, diagnostic.range)],
+                        "relatedLocations": related_locations,
+                    })
+                })
+            },
+        )
+        .collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_HOMEPAGE"),
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": sarif_results,
+        }],
+    })
+}
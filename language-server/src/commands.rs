@@ -0,0 +1,22 @@
+use strum_macros::{AsRefStr, EnumIter, EnumString};
+
+#[derive(EnumIter, EnumString, AsRefStr)]
+/// The commands the server can execute via `workspace/executeCommand`.
+pub enum Command {
+    /// Parses an input string with a rule of a tracked grammar.
+    ///
+    /// Arguments: `[grammar_uri, rule, input]`.
+    #[strum(serialize = "pest.parseInput")]
+    ParseInput,
+    /// Generates a reference for the grammar unit of a tracked grammar, as `pest-language-server
+    /// doc` does.
+    ///
+    /// Arguments: `[grammar_uri, format?]`, where `format` is `markdown` (the default) or
+    /// `html`.
+    #[strum(serialize = "pest.generateDocs")]
+    GenerateDocs,
+}
+
+/// A command implemented by the editor extension rather than the server, as the server cannot
+/// change the editor's settings. Adds the rule name argument to `alwaysUsedRuleNames`.
+pub const ADD_ALWAYS_USED_RULE_NAME: &str = "pestIdeTools.addAlwaysUsedRuleName";
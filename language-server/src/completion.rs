@@ -0,0 +1,328 @@
+use std::iter;
+
+use pest_meta::ast::RuleType;
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, InsertTextFormat, Position, Range,
+    TextEdit,
+};
+
+use crate::helpers::is_identifier_char;
+
+/// A rule modifier.
+pub struct Modifier {
+    pub symbol: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// The rule modifiers.
+pub const MODIFIERS: [Modifier; 4] = [
+    Modifier {
+        symbol: "_",
+        name: "silent",
+        description: "Silent: the rule does not produce a token pair",
+    },
+    Modifier {
+        symbol: "@",
+        name: "atomic",
+        description: "Atomic: no implicit whitespace, and inner rules are silent",
+    },
+    Modifier {
+        symbol: "$",
+        name: "compound-atomic",
+        description: "Compound-atomic: no implicit whitespace, but inner rules produce tokens",
+    },
+    Modifier {
+        symbol: "!",
+        name: "non-atomic",
+        description: "Non-atomic: restores implicit whitespace inside an atomic rule",
+    },
+];
+
+impl Modifier {
+    /// Returns the modifier of a rule type, if it has one.
+    pub fn of(rule_type: RuleType) -> Option<&'static Modifier> {
+        let symbol = match rule_type {
+            RuleType::Normal => return None,
+            RuleType::Silent => "_",
+            RuleType::Atomic => "@",
+            RuleType::CompoundAtomic => "$",
+            RuleType::NonAtomic => "!",
+        };
+
+        MODIFIERS.iter().find(|modifier| modifier.symbol == symbol)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+/// What can be completed at a position in a grammar.
+pub enum CompletionContext<'a> {
+    /// Nothing, e.g. inside a string, a character range, or a comment.
+    None,
+    /// A new rule, with the partially typed identifier before the position.
+    Rule(&'a str),
+    /// A rule or builtin in a rule's expression, with the partially typed identifier before
+    /// the position.
+    Expression(&'a str),
+    /// A modifier or the opening brace, after the `=` of a rule header.
+    Modifier,
+    /// The opening brace, after the modifier of a rule header.
+    Body,
+}
+
+/// The lexical state of the grammar at a position.
+enum Lexical {
+    Code,
+    String,
+    Character,
+    LineComment,
+    /// A block comment, at a nesting depth.
+    BlockComment(usize),
+}
+
+/// Determines what can be completed at a byte offset in a grammar, from the text before it.
+/// This does not rely on the grammar parsing, as it rarely does while it is being typed.
+pub fn completion_context(text: &str, offset: usize) -> CompletionContext<'_> {
+    let before = &text[..offset];
+    let mut state = Lexical::Code;
+    let mut depth = 0_usize;
+    // The code of the top-level rule header being typed, i.e. since the end of the last rule.
+    let mut header = String::new();
+
+    let mut chars = before.chars().peekable();
+    while let Some(c) = chars.next() {
+        state = match state {
+            Lexical::Code => match c {
+                '"' => Lexical::String,
+                '\'' => Lexical::Character,
+                '/' if chars.next_if_eq(&'/').is_some() => Lexical::LineComment,
+                '/' if chars.next_if_eq(&'*').is_some() => Lexical::BlockComment(1),
+                '{' => {
+                    depth += 1;
+                    Lexical::Code
+                }
+                '}' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        header.clear();
+                    }
+                    Lexical::Code
+                }
+                _ => {
+                    if depth == 0 {
+                        header.push(c);
+                    }
+                    Lexical::Code
+                }
+            },
+            Lexical::String | Lexical::Character if c == '\\' => {
+                chars.next();
+                state
+            }
+            Lexical::String if c == '"' => Lexical::Code,
+            Lexical::Character if c == '\'' => Lexical::Code,
+            Lexical::LineComment if c == '\n' => Lexical::Code,
+            Lexical::BlockComment(nesting) if c == '/' && chars.next_if_eq(&'*').is_some() => {
+                Lexical::BlockComment(nesting + 1)
+            }
+            Lexical::BlockComment(nesting) if c == '*' && chars.next_if_eq(&'/').is_some() => {
+                match nesting {
+                    1 => Lexical::Code,
+                    _ => Lexical::BlockComment(nesting - 1),
+                }
+            }
+            state => state,
+        };
+    }
+
+    if !matches!(state, Lexical::Code) {
+        return CompletionContext::None;
+    }
+
+    if depth > 0 {
+        let partial_start = before.trim_end_matches(is_identifier_char).len();
+        let partial = &before[partial_start..];
+        // Tags and repetition bounds are not references.
+        if before[..partial_start].ends_with('#')
+            || partial.starts_with(|c: char| c.is_ascii_digit())
+        {
+            return CompletionContext::None;
+        }
+
+        return CompletionContext::Expression(partial);
+    }
+
+    let Some((name, rest)) = header.rsplit_once('=') else {
+        let partial = &before[before.trim_end_matches(is_identifier_char).len()..];
+        return match header.trim_start() == partial {
+            true => CompletionContext::Rule(partial),
+            false => CompletionContext::None,
+        };
+    };
+
+    let name = name.trim_end();
+    if !is_identifier(&name[name.trim_end_matches(is_identifier_char).len()..]) {
+        return CompletionContext::None;
+    }
+
+    match rest.trim() {
+        "" => CompletionContext::Modifier,
+        rest if MODIFIERS.iter().any(|modifier| modifier.symbol == rest) => CompletionContext::Body,
+        _ => CompletionContext::None,
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| !c.is_ascii_digit()) && s.chars().all(is_identifier_char)
+}
+
+/// Scores how well `candidate` matches a partially typed `pattern`, if all of the characters
+/// of the pattern appear in it in order, ignoring case. Higher is better: matches at the start
+/// of the candidate or of one of its words, and runs of consecutive characters, score highest.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i32> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+
+    for p in pattern.chars() {
+        let index = (next..candidate.len())
+            .find(|&index| candidate[index].to_lowercase().eq(p.to_lowercase()))?;
+        let c = candidate[index];
+
+        score += if c == p { 2 } else { 1 };
+        if index == 0 {
+            score += 8;
+        } else if candidate[index - 1] == '_'
+            || (c.is_uppercase() && candidate[index - 1].is_lowercase())
+        {
+            score += 6;
+        }
+
+        match previous {
+            Some(previous) if previous + 1 == index => score += 4,
+            Some(previous) => score -= (index - previous - 1).min(3) as i32,
+            None => score -= index.min(3) as i32,
+        }
+
+        previous = Some(index);
+        next = index + 1;
+    }
+
+    // Prefer the shorter of otherwise equal matches.
+    Some(score * 4 - (candidate.len() - next).min(3) as i32)
+}
+
+/// Returns the completions for a rule header: the modifiers, if `modifiers` is set, and the
+/// opening brace of the rule's expression.
+pub fn header_completions(position: Position, modifiers: bool) -> Vec<CompletionItem> {
+    // The items are inserted at the position, rather than replacing the word before it, so
+    // the client does not filter the brace out after a `_` modifier.
+    let item = |label: &str, detail: &str, kind| CompletionItem {
+        label: label.to_owned(),
+        kind: Some(kind),
+        detail: Some(detail.to_owned()),
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+            Range::new(position, position),
+            label.to_owned(),
+        ))),
+        ..Default::default()
+    };
+
+    MODIFIERS
+        .iter()
+        .filter(|_| modifiers)
+        .map(|modifier| {
+            item(
+                modifier.symbol,
+                modifier.description,
+                CompletionItemKind::KEYWORD,
+            )
+        })
+        .chain(iter::once(item(
+            "{",
+            "Opens the rule's expression",
+            CompletionItemKind::OPERATOR,
+        )))
+        .collect()
+}
+
+/// A snippet for a common grammar idiom, in the LSP snippet syntax.
+pub struct Snippet {
+    pub label: &'static str,
+    pub detail: &'static str,
+    pub body: &'static str,
+}
+
+/// The snippets offered in place of a new rule.
+pub const RULE_SNIPPETS: [Snippet; 7] = [
+    Snippet {
+        label: "rule",
+        detail: "Rule definition",
+        body: "${1:name} = { $0 }",
+    },
+    Snippet {
+        label: "root",
+        detail: "Rule matching the entire input",
+        body: "${1:file} = { SOI ~ ${2:rule} ~ EOI }",
+    },
+    Snippet {
+        label: "WHITESPACE",
+        detail: "Implicit whitespace between the tokens of non-atomic rules",
+        body: r#"WHITESPACE = _{ " " | "\\t" | NEWLINE }"#,
+    },
+    Snippet {
+        label: "COMMENT",
+        detail: "Implicit line and block comments between the tokens of non-atomic rules",
+        body: r#"COMMENT = _{ "//" ~ (!NEWLINE ~ ANY)* | "/*" ~ (!"*/" ~ ANY)* ~ "*/" }"#,
+    },
+    Snippet {
+        label: "list",
+        detail: "Delimited list",
+        body: r#"${1:list} = { ${2:item} ~ ("${3:,}" ~ ${2:item})* }"#,
+    },
+    Snippet {
+        label: "string",
+        detail: "String literal with escape sequences",
+        body: r#"${1:string} = \${ "\\"" ~ ${1:string}_inner ~ "\\"" }
+${1:string}_inner = @{ ${1:string}_char* }
+${1:string}_char = {
+    !("\\"" | "\\\\") ~ ANY
+    | "\\\\" ~ ("\\"" | "\\\\" | "/" | "b" | "f" | "n" | "r" | "t")
+    | "\\\\" ~ "u" ~ ASCII_HEX_DIGIT{4}
+}"#,
+    },
+    Snippet {
+        label: "raw_string",
+        detail: "Raw string literal delimited by any number of #, using the stack",
+        body: r##"${1:raw_string} = \${ "r" ~ PUSH("#"*) ~ "\\"" ~ ${1:raw_string}_inner ~ "\\"" ~ POP }
+${1:raw_string}_inner = @{ (!("\\"" ~ PEEK) ~ ANY)* }"##,
+    },
+];
+
+/// The snippets offered inside a rule's expression.
+pub const EXPRESSION_SNIPPETS: [Snippet; 2] = [
+    Snippet {
+        label: "root",
+        detail: "Match the entire input",
+        body: "SOI ~ ${1:rule} ~ EOI",
+    },
+    Snippet {
+        label: "list",
+        detail: "Delimited list",
+        body: r#"${1:item} ~ ("${2:,}" ~ ${1:item})*"#,
+    },
+];
+
+impl Snippet {
+    pub fn completion_item(&self) -> CompletionItem {
+        CompletionItem {
+            label: self.label.to_owned(),
+            kind: Some(CompletionItemKind::SNIPPET),
+            detail: Some(self.detail.to_owned()),
+            insert_text: Some(self.body.to_owned()),
+            insert_text_format: Some(InsertTextFormat::SNIPPET),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use pest::{
+    error::{Error, ErrorVariant, InputLocation},
+    iterators::{Pair, Pairs},
+};
+use pest_meta::parser::Rule;
+use tower_lsp::lsp_types::{DiagnosticRelatedInformation, Location};
+
+/// Builtins that can succeed without consuming input.
+const EMPTY_BUILTINS: [&str; 3] = ["SOI", "EOI", "DROP"];
+
+/// Explains validation errors caused by cycles in a grammar, i.e. left recursion and
+/// repetitions that never consume input, in terms of the rules that form them.
+pub struct Cycles<'a> {
+    pairs: Pairs<'a, Rule>,
+    /// The expression of every rule, by rule name.
+    expressions: HashMap<&'a str, Pair<'a, Rule>>,
+}
+
+impl<'a> Cycles<'a> {
+    pub fn new(pairs: Pairs<'a, Rule>) -> Self {
+        let expressions = pairs
+            .clone()
+            .filter(|pair| pair.as_rule() == Rule::grammar_rule)
+            .filter_map(|pair| {
+                let mut inner = pair.into_inner();
+                let identifier = inner.next().filter(|p| p.as_rule() == Rule::identifier)?;
+                let expression = inner.find(|p| p.as_rule() == Rule::expression)?;
+                Some((identifier.as_str(), expression))
+            })
+            .collect();
+
+        Self { pairs, expressions }
+    }
+
+    /// Lists the rules that form the cycle behind `error`, if it is caused by one. `locate`
+    /// returns the location of a rule's name, which may be in another document of the unit.
+    pub fn related_information(
+        &self,
+        error: &Error<Rule>,
+        locate: impl Fn(&str) -> Option<Location>,
+    ) -> Option<Vec<DiagnosticRelatedInformation>> {
+        let ErrorVariant::CustomError { message } = &error.variant else {
+            return None;
+        };
+
+        let explanations = if let Some(chain) = left_recursion_chain(message) {
+            chain
+                .windows(2)
+                .map(|calls| {
+                    let (caller, callee) = (calls[0], calls[1]);
+                    (
+                        caller,
+                        format!("{caller} calls {callee} before consuming input"),
+                    )
+                })
+                .collect()
+        } else if message.ends_with("will repeat infinitely") {
+            self.empty_match_explanations(&error.location)
+        } else {
+            return None;
+        };
+
+        let related_information: Vec<_> = explanations
+            .into_iter()
+            .filter_map(|(rule, message)| {
+                Some(DiagnosticRelatedInformation {
+                    location: locate(rule)?,
+                    message,
+                })
+            })
+            .collect();
+
+        (!related_information.is_empty()).then_some(related_information)
+    }
+
+    /// Returns the chain of rules through which `rule` can match without consuming input,
+    /// starting with `rule` itself, or [None] if it always consumes input.
+    pub fn empty_match_trace(&self, rule: &str) -> Option<Vec<&'a str>> {
+        self.rule_trace(rule, &mut Vec::new())
+    }
+
+    /// Explains why the rules referenced in the span of a repetition error can match without
+    /// consuming input.
+    fn empty_match_explanations(&self, location: &InputLocation) -> Vec<(&'a str, String)> {
+        let (start, end) = match *location {
+            InputLocation::Pos(pos) => (pos, pos),
+            InputLocation::Span(span) => span,
+        };
+
+        let mut explanations: Vec<(&str, String)> = Vec::new();
+        let identifiers = self.pairs.clone().flatten().filter(|pair| {
+            pair.as_rule() == Rule::identifier
+                && pair.as_span().start() >= start
+                && pair.as_span().end() <= end
+        });
+
+        for identifier in identifiers {
+            let Some(trace) = self.empty_match_trace(identifier.as_str()) else {
+                continue;
+            };
+
+            for (index, rule) in trace.iter().enumerate() {
+                if explanations.iter().any(|(explained, _)| explained == rule) {
+                    continue;
+                }
+
+                let message = match trace.get(index + 1) {
+                    Some(next) => format!("{rule} can match empty input through {next}"),
+                    None => format!("{rule} can match empty input"),
+                };
+                explanations.push((rule, message));
+            }
+        }
+
+        explanations
+    }
+
+    fn rule_trace(&self, rule: &str, visited: &mut Vec<&'a str>) -> Option<Vec<&'a str>> {
+        let Some((&name, expression)) = self.expressions.get_key_value(rule) else {
+            return EMPTY_BUILTINS.contains(&rule).then(Vec::new);
+        };
+
+        if visited.contains(&name) {
+            return None;
+        }
+
+        visited.push(name);
+        let trace = self.expression_trace(expression.clone(), visited);
+        visited.pop();
+
+        trace.map(|mut trace| {
+            trace.insert(0, name);
+            trace
+        })
+    }
+
+    fn expression_trace(
+        &self,
+        expression: Pair<'a, Rule>,
+        visited: &mut Vec<&'a str>,
+    ) -> Option<Vec<&'a str>> {
+        // Sequences bind tighter than choices, so split the terms into alternatives.
+        let mut alternatives = vec![Vec::new()];
+        for pair in expression.into_inner() {
+            match pair.as_rule() {
+                Rule::choice_operator => alternatives.push(Vec::new()),
+                Rule::term => alternatives.last_mut().expect("not empty").push(pair),
+                _ => {}
+            }
+        }
+
+        alternatives
+            .into_iter()
+            .filter(|terms| !terms.is_empty())
+            .find_map(|terms| {
+                let mut trace = Vec::new();
+                for term in terms {
+                    trace.extend(self.term_trace(term, visited)?);
+                }
+                Some(trace)
+            })
+    }
+
+    fn term_trace(&self, term: Pair<'a, Rule>, visited: &mut Vec<&'a str>) -> Option<Vec<&'a str>> {
+        let mut node = None;
+        let mut optional = false;
+
+        for pair in term.into_inner() {
+            match pair.as_rule() {
+                Rule::positive_predicate_operator
+                | Rule::negative_predicate_operator
+                | Rule::optional_operator
+                | Rule::repeat_operator
+                | Rule::repeat_max => optional = true,
+                Rule::repeat_exact | Rule::repeat_min | Rule::repeat_min_max => {
+                    let min = pair.into_inner().find(|p| p.as_rule() == Rule::number);
+                    optional |= min.is_some_and(|min| min.as_str().parse() == Ok(0));
+                }
+                Rule::repeat_once_operator
+                | Rule::tag_id
+                | Rule::assignment_operator
+                | Rule::opening_paren
+                | Rule::closing_paren => {}
+                _ => node = Some(pair),
+            }
+        }
+
+        if optional {
+            return Some(Vec::new());
+        }
+
+        let node = node?;
+        match node.as_rule() {
+            Rule::expression => self.expression_trace(node, visited),
+            Rule::identifier => self.rule_trace(node.as_str(), visited),
+            Rule::string => (node.as_str() == "\"\"").then(Vec::new),
+            Rule::insensitive_string => (node.as_str() == "^\"\"").then(Vec::new),
+            Rule::_push => {
+                let expression = node
+                    .into_inner()
+                    .find(|p| p.as_rule() == Rule::expression)?;
+                self.expression_trace(expression, visited)
+            }
+            Rule::_push_literal => Some(Vec::new()),
+            _ => None,
+        }
+    }
+}
+
+/// Extracts the chain of rules from a pest_meta left recursion error, e.g.
+/// `rule a is left-recursive (a -> b -> a); ...`.
+fn left_recursion_chain(message: &str) -> Option<Vec<&str>> {
+    let (_, rest) = message.split_once("is left-recursive (")?;
+    let (chain, _) = rest.split_once(')')?;
+    Some(chain.split(" -> ").collect())
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+};
+
+use clap::{Arg, ArgMatches, Command, value_parser};
+use pest_meta::parser::{self, Rule};
+use strum_macros::EnumString;
+use tower_lsp::lsp_types::TextDocumentItem;
+
+use crate::{
+    analysis::{Analysis, RuleAnalysis},
+    check::read_document,
+    completion::Modifier,
+    helpers::error_diagnostic,
+    line_index::{LineIndex, PositionEncoding},
+};
+
+#[derive(Debug, Clone, Copy, EnumString)]
+#[strum(serialize_all = "lowercase")]
+/// The formats grammar documentation can be generated in.
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+/// The documentation of a single rule.
+struct RuleDocs<'a> {
+    name: &'a str,
+    ra: &'a RuleAnalysis,
+    /// The rules of the unit the rule calls, by name.
+    calls: BTreeSet<&'a str>,
+    /// The rules of the unit that call the rule, by name.
+    callers: BTreeSet<&'a str>,
+}
+
+/// The documentation of a single grammar file.
+struct GrammarDocs<'a> {
+    /// The name of the file.
+    name: String,
+    /// The `//!` comments of the grammar.
+    doc: Option<String>,
+    /// The rules of the grammar, in the order they are defined.
+    rules: Vec<RuleDocs<'a>>,
+}
+
+/// Returns the `doc` subcommand, which generates documentation for grammars.
+pub fn subcommand() -> Command {
+    Command::new("doc")
+        .about("Generate a reference for grammars, from their doc comments")
+        .arg(
+            Arg::new("files")
+                .help("The grammar files to document, as a single grammar unit")
+                .required(true)
+                .num_args(1..)
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("format")
+                .help("The output format")
+                .long("format")
+                .value_parser(["markdown", "html"])
+                .default_value("markdown"),
+        )
+        .arg(
+            Arg::new("title")
+                .help("The title of the reference [default: the name of the first file]")
+                .long("title"),
+        )
+        .arg(
+            Arg::new("output")
+                .help("The file to write the reference to [default: stdout]")
+                .long("output")
+                .short('o')
+                .value_parser(value_parser!(PathBuf)),
+        )
+}
+
+/// Runs the `doc` subcommand.
+pub fn run(matches: &ArgMatches) -> ExitCode {
+    let paths: Vec<_> = matches
+        .get_many::<PathBuf>("files")
+        .unwrap_or_default()
+        .collect();
+
+    let mut documents = Vec::new();
+    for path in &paths {
+        let document = match read_document(path) {
+            Ok(document) => document,
+            Err(e) => {
+                eprintln!("{}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        };
+
+        // Columns are reported in characters, as by `check`.
+        let index = LineIndex::new(&document.text, PositionEncoding::Utf32);
+        let analysis = match parser::parse(Rule::grammar_rules, &document.text) {
+            Ok(pairs) => Analysis::new(pairs, &index, None),
+            Err(error) => {
+                let diagnostic = error_diagnostic(&error, &index);
+                let start = diagnostic.range.start;
+                eprintln!(
+                    "{}:{}:{}: error: {}",
+                    path.display(),
+                    start.line + 1,
+                    start.character + 1,
+                    diagnostic.message
+                );
+                return ExitCode::FAILURE;
+            }
+        };
+
+        documents.push((document, analysis));
+    }
+
+    let title = matches
+        .get_one::<String>("title")
+        .cloned()
+        .unwrap_or_else(|| {
+            paths[0]
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        });
+    let format = matches
+        .get_one::<String>("format")
+        .and_then(|format| DocFormat::from_str(format).ok())
+        .unwrap_or(DocFormat::Markdown);
+
+    let documents: Vec<_> = documents
+        .iter()
+        .map(|(document, analysis)| (document, analysis))
+        .collect();
+    let output = generate(&title, &documents, format);
+
+    match matches.get_one::<PathBuf>("output") {
+        Some(path) => {
+            if let Err(e) = fs::write(path, output) {
+                eprintln!("{}: {e}", path.display());
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{output}"),
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Generates a reference for a grammar unit: the grammar documentation of each of its
+/// documents, followed by every rule with its documentation, definition, and links to the rules
+/// it calls and is called by.
+pub fn generate(
+    title: &str,
+    documents: &[(&TextDocumentItem, &Analysis)],
+    format: DocFormat,
+) -> String {
+    let defined: HashSet<&str> = documents
+        .iter()
+        .flat_map(|(_, analysis)| analysis.rules.keys())
+        .map(String::as_str)
+        .collect();
+
+    let mut calls: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    let mut callers: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for (caller, callee) in documents
+        .iter()
+        .flat_map(|(_, analysis)| analysis.calls())
+        .filter(|(_, callee)| defined.contains(callee))
+    {
+        calls.entry(caller).or_default().insert(callee);
+        callers.entry(callee).or_default().insert(caller);
+    }
+
+    let grammars: Vec<_> = documents
+        .iter()
+        .map(|(document, analysis)| {
+            let mut rules: Vec<_> = analysis
+                .rules
+                .iter()
+                .map(|(name, ra)| RuleDocs {
+                    name,
+                    ra,
+                    calls: calls.remove(name.as_str()).unwrap_or_default(),
+                    callers: callers.remove(name.as_str()).unwrap_or_default(),
+                })
+                .collect();
+            rules.sort_by_key(|rule| rule.ra.definition_location.start);
+
+            GrammarDocs {
+                name: document
+                    .uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .unwrap_or_default()
+                    .to_owned(),
+                doc: analysis.doc.clone(),
+                rules,
+            }
+        })
+        .collect();
+
+    match format {
+        DocFormat::Markdown => markdown(title, &grammars),
+        DocFormat::Html => html(title, &grammars),
+    }
+}
+
+/// Returns the definition of a rule, as it would be written in the grammar.
+fn definition(rule: &RuleDocs) -> String {
+    format!(
+        "{} = {}{{ {} }}",
+        rule.name,
+        Modifier::of(rule.ra.rule_type).map_or("", |modifier| modifier.symbol),
+        rule.ra.expression.trim()
+    )
+}
+
+fn markdown(title: &str, grammars: &[GrammarDocs]) -> String {
+    let mut output = format!("# {title}\n");
+    let link = |name: &&str| format!("[`{name}`](#{name})");
+
+    for grammar in grammars {
+        let heading = if grammars.len() > 1 {
+            output.push_str(&format!("\n## {}\n", grammar.name));
+            "###"
+        } else {
+            "##"
+        };
+
+        if let Some(doc) = &grammar.doc {
+            output.push_str(&format!("\n{doc}\n"));
+        }
+
+        for rule in &grammar.rules {
+            output.push_str(&format!(
+                "\n<a id=\"{0}\"></a>\n\n{heading} `{0}`\n",
+                rule.name
+            ));
+            if let Some(doc) = &rule.ra.doc {
+                output.push_str(&format!("\n{doc}\n"));
+            }
+
+            output.push_str(&format!("\n```pest\n{}\n```\n", definition(rule)));
+            if let Some(modifier) = Modifier::of(rule.ra.rule_type) {
+                output.push_str(&format!("\n{}\n", modifier.description));
+            }
+
+            if !rule.calls.is_empty() {
+                let calls: Vec<_> = rule.calls.iter().map(link).collect();
+                output.push_str(&format!("\nCalls: {}\n", calls.join(", ")));
+            }
+            if !rule.callers.is_empty() {
+                let callers: Vec<_> = rule.callers.iter().map(link).collect();
+                output.push_str(&format!("\nCalled by: {}\n", callers.join(", ")));
+            }
+        }
+    }
+
+    output
+}
+
+fn html(title: &str, grammars: &[GrammarDocs]) -> String {
+    let mut output = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+         </head>\n<body>\n<h1>{0}</h1>\n",
+        escape(title)
+    );
+    let link = |name: &&str| format!("<a href=\"#{0}\"><code>{0}</code></a>", escape(name));
+
+    for grammar in grammars {
+        let heading = if grammars.len() > 1 {
+            output.push_str(&format!("<h2>{}</h2>\n", escape(&grammar.name)));
+            "h3"
+        } else {
+            "h2"
+        };
+
+        if let Some(doc) = &grammar.doc {
+            output.push_str(&paragraphs(doc));
+        }
+
+        for rule in &grammar.rules {
+            output.push_str(&format!(
+                "<{heading} id=\"{0}\"><code>{0}</code></{heading}>\n",
+                escape(rule.name)
+            ));
+            if let Some(doc) = &rule.ra.doc {
+                output.push_str(&paragraphs(doc));
+            }
+
+            output.push_str(&format!(
+                "<pre><code>{}</code></pre>\n",
+                escape(&definition(rule))
+            ));
+            if let Some(modifier) = Modifier::of(rule.ra.rule_type) {
+                output.push_str(&format!("<p>{}</p>\n", escape(modifier.description)));
+            }
+
+            if !rule.calls.is_empty() {
+                let calls: Vec<_> = rule.calls.iter().map(link).collect();
+                output.push_str(&format!("<p>Calls: {}</p>\n", calls.join(", ")));
+            }
+            if !rule.callers.is_empty() {
+                let callers: Vec<_> = rule.callers.iter().map(link).collect();
+                output.push_str(&format!("<p>Called by: {}</p>\n", callers.join(", ")));
+            }
+        }
+    }
+
+    output.push_str("</body>\n</html>\n");
+    output
+}
+
+/// Splits documentation into HTML paragraphs at blank lines.
+fn paragraphs(doc: &str) -> String {
+    doc.split("\n\n")
+        .filter(|paragraph| !paragraph.trim().is_empty())
+        .map(|paragraph| format!("<p>{}</p>\n", escape(paragraph.trim())))
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
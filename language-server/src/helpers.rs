@@ -1,56 +1,90 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use pest::{
     Span,
-    error::{Error, ErrorVariant, LineColLocation},
+    error::{Error, ErrorVariant, InputLocation},
     iterators::Pairs,
 };
 use pest_meta::{
+    ast::Rule as AstRule,
     parser::{self, Rule},
     validator,
 };
 use tower_lsp::lsp_types::{
-    Diagnostic, DiagnosticSeverity, Position, PublishDiagnosticsParams, Range, TextDocumentItem,
-    Url,
+    Diagnostic, DiagnosticSeverity, PublishDiagnosticsParams, Range, TextDocumentItem, Url,
 };
-use unicode_segmentation::UnicodeSegmentation;
+
+use crate::line_index::LineIndex;
 
 pub type Documents = HashMap<Url, TextDocumentItem>;
 pub type Diagnostics = Vec<PublishDiagnosticsParams>;
 
+/// Returns whether a document is a grammar, rather than an input to one.
+pub fn is_grammar(document: &TextDocumentItem) -> bool {
+    document.language_id == "pest" || document.uri.path().ends_with(".pest")
+}
+
+/// Directories that hold build output or vendored code, rather than sources.
+const SKIPPED_DIRECTORIES: [&str; 2] = ["target", "node_modules"];
+
+/// Finds the files with an `extension` under `root`, skipping build output, vendored code, and
+/// hidden directories. Symbolic links to directories are not followed, as they can form loops.
+pub fn find_files(root: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = fs::read_dir(root) else {
+        return files;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if file_type.is_dir() {
+            if !SKIPPED_DIRECTORIES.contains(&name.as_ref()) && !name.starts_with('.') {
+                files.extend(find_files(&path, extension));
+            }
+        } else if path.extension().is_some_and(|e| e == extension) && path.is_file() {
+            files.push(path);
+        }
+    }
+
+    files
+}
+
+/// Converts a location in a text to a range, in the encoding of an index of the same text.
 pub trait IntoRange {
-    fn into_range(self) -> Range;
+    fn into_range(self, index: &LineIndex) -> Range;
 }
 
-impl IntoRange for LineColLocation {
-    fn into_range(self) -> Range {
+impl IntoRange for InputLocation {
+    fn into_range(self, index: &LineIndex) -> Range {
         match self {
-            LineColLocation::Pos((line, col)) => {
-                let pos = Position::new(line as u32 - 1, col as u32 - 1);
-                Range::new(pos, pos)
-            }
-            LineColLocation::Span((start_line, start_col), (end_line, end_col)) => Range::new(
-                Position::new(start_line as u32 - 1, start_col as u32 - 1),
-                Position::new(end_line as u32 - 1, end_col as u32 - 1),
-            ),
+            InputLocation::Pos(pos) => index.range(pos..pos),
+            InputLocation::Span((start, end)) => index.range(start..end),
         }
     }
 }
 
 impl IntoRange for Span<'_> {
-    fn into_range(self) -> Range {
-        let start = self.start_pos().line_col();
-        let end = self.end_pos().line_col();
-        LineColLocation::Span((start.0, start.1), (end.0, end.1)).into_range()
+    fn into_range(self, index: &LineIndex) -> Range {
+        index.range(self.start()..self.end())
     }
 }
 
 pub trait FindReferences<'a> {
-    fn find_references(self, definition: Span<'a>) -> Vec<Range>;
+    fn find_references(self, definition: Span<'a>, index: &LineIndex) -> Vec<Range>;
 }
 
 impl<'a> FindReferences<'a> for Pairs<'a, Rule> {
-    fn find_references(self, definition: Span<'a>) -> Vec<Range> {
+    fn find_references(self, definition: Span<'a>, index: &LineIndex) -> Vec<Range> {
         let mut ranges = vec![];
 
         for pair in self {
@@ -58,94 +92,44 @@ impl<'a> FindReferences<'a> for Pairs<'a, Rule> {
                 && pair.as_span() != definition
                 && pair.as_str() == definition.as_str()
             {
-                ranges.push(pair.as_span().into_range());
+                ranges.push(pair.as_span().into_range(index));
             }
 
             let inner = pair.into_inner();
-            ranges.extend(inner.find_references(definition));
+            ranges.extend(inner.find_references(definition, index));
         }
 
         ranges
     }
 }
 
-pub trait IntoRangeWithLine {
-    fn into_range(self, line: u32) -> Range;
-}
-
-impl IntoRangeWithLine for std::ops::Range<usize> {
-    fn into_range(self, line: u32) -> Range {
-        let start = Position::new(line, self.start as u32);
-        let end = Position::new(line, self.end as u32);
-        Range::new(start, end)
-    }
-}
-
-pub trait FindWordRange {
-    fn word_range_at_idx(self, idx: usize) -> std::ops::Range<usize>;
-}
-
-impl FindWordRange for &str {
-    fn word_range_at_idx(self, search_idx: usize) -> std::ops::Range<usize> {
-        fn is_not_identifier(c: char) -> bool {
-            c.is_whitespace()
-                || c == '*'
-                || c == '+'
-                || c == '?'
-                || c == '!'
-                || c == '&'
-                || c == '~'
-                || c == '{'
-                || c == '}'
-                || c == '['
-                || c == ']'
-                || c == '('
-                || c == ')'
-        }
-
-        let next = str_range(self, &(search_idx..self.len()))
-            .graphemes(true)
-            .enumerate()
-            .find(|(_index, char)| is_not_identifier(char.chars().next().unwrap_or(' ')))
-            .map(|(index, _char)| index)
-            .map(|index| search_idx + index)
-            .unwrap_or(self.len());
-
-        let preceding = str_range(self, &(0..search_idx))
-            .graphemes(true)
-            .rev()
-            .enumerate()
-            .find(|(_index, char)| is_not_identifier(char.chars().next().unwrap_or(' ')))
-            .map(|(index, _char)| index)
-            .map(|index| search_idx - index)
-            .unwrap_or(0);
-
-        preceding..next
-    }
+/// Returns the byte range of the identifier around a byte offset in `text`, which is empty if
+/// there is none.
+pub fn identifier_at(text: &str, offset: usize) -> std::ops::Range<usize> {
+    let start = text[..offset].trim_end_matches(is_identifier_char).len();
+    let end =
+        offset + text[offset..].len() - text[offset..].trim_start_matches(is_identifier_char).len();
+    start..end
 }
 
-/// Returns a string from a range of human characters (graphemes). Respects unicode.
-pub fn str_range(s: &str, range: &std::ops::Range<usize>) -> String {
-    s.graphemes(true)
-        .skip(range.start)
-        .take(range.len())
-        .collect()
+pub fn is_identifier_char(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
 }
 
 pub trait IntoDiagnostics {
-    fn into_diagnostics(self) -> Vec<Diagnostic>;
+    fn into_diagnostics(self, index: &LineIndex) -> Vec<Diagnostic>;
 }
 
 impl IntoDiagnostics for Vec<pest::error::Error<Rule>> {
-    fn into_diagnostics(self) -> Vec<Diagnostic> {
-        self.iter().map(error_diagnostic).collect()
+    fn into_diagnostics(self, index: &LineIndex) -> Vec<Diagnostic> {
+        self.iter().map(|e| error_diagnostic(e, index)).collect()
     }
 }
 
-fn error_diagnostic(e: &Error<Rule>) -> Diagnostic {
+pub fn error_diagnostic(e: &Error<Rule>, index: &LineIndex) -> Diagnostic {
     let message = error_message(e);
     Diagnostic {
-        range: e.line_col.clone().into_range(),
+        range: e.location.clone().into_range(index),
         severity: Some(DiagnosticSeverity::ERROR),
         source: Some("Pest Language Server".to_owned()),
         message,
@@ -189,11 +173,10 @@ fn parsing_error(positives: &[Rule], negatives: &[Rule]) -> String {
     format!("Parsing error{expected}{unexpected}")
 }
 
-pub fn validate_pairs(pairs: Pairs<'_, Rule>) -> Result<(), Vec<Error<Rule>>> {
+pub fn validate_pairs(pairs: Pairs<'_, Rule>) -> Result<Vec<AstRule>, Vec<Error<Rule>>> {
     validator::validate_pairs(pairs.clone())?;
     // This calls validator::validate_ast under the hood
-    parser::consume_rules(pairs)?;
-    Ok(())
+    parser::consume_rules(pairs)
 }
 
 pub trait RangeContains {
@@ -202,9 +185,6 @@ pub trait RangeContains {
 
 impl RangeContains for Range {
     fn contains(&self, other: Self) -> bool {
-        self.start.line <= other.start.line
-            && self.start.character <= other.start.character
-            && self.end.line >= other.end.line
-            && self.end.character >= other.end.character
+        self.start <= other.start && self.end >= other.end
     }
 }
@@ -0,0 +1,179 @@
+use std::collections::{HashMap, VecDeque};
+
+use pest::iterators::Pairs;
+use pest_meta::{ast::RuleType, parser::Rule};
+use tower_lsp::lsp_types::{
+    InlayHint, InlayHintKind, InlayHintLabel, InlayHintTooltip, MarkupContent, MarkupKind,
+    Position, Range,
+};
+
+use crate::{analysis::Analysis, completion::Modifier, helpers::IntoRange, line_index::LineIndex};
+
+/// Returns the inlay hints for the part of a grammar in `range`: the modifier of each
+/// referenced rule, and the whitespace pest implicitly matches between the sequenced tokens of
+/// non-atomic rules. `index` indexes the grammar's text, and `unit` holds the analyses of the
+/// documents of its unit.
+pub fn inlay_hints(
+    pairs: Pairs<Rule>,
+    index: &LineIndex,
+    range: Range,
+    unit: &[&Analysis],
+) -> Vec<InlayHint> {
+    let rule_types: HashMap<&str, RuleType> = unit
+        .iter()
+        .flat_map(|analysis| &analysis.rules)
+        .map(|(name, ra)| (name.as_str(), ra.rule_type))
+        .collect();
+    let skip = skip_label(&rule_types);
+    let atomic_callers = atomic_callers(unit, &rule_types);
+    let in_range = |position: Position| range.start <= position && position <= range.end;
+
+    let mut hints = Vec::new();
+    for pair in pairs.filter(|pair| pair.as_rule() == Rule::grammar_rule) {
+        let Some(identifier) = pair.clone().into_inner().next() else {
+            continue;
+        };
+
+        let name = identifier.as_str();
+        let whitespace_tooltip = skip
+            .as_ref()
+            .filter(|_| identifier.as_rule() == Rule::identifier)
+            .and_then(|skip| {
+                whitespace_tooltip(name, rule_types.get(name)?, &atomic_callers, skip)
+            });
+
+        for pair in pair.into_inner().flatten() {
+            let position = pair.as_span().into_range(index).end;
+            if !in_range(position) {
+                continue;
+            }
+
+            match pair.as_rule() {
+                Rule::identifier if pair.as_span() != identifier.as_span() => {
+                    let Some(modifier) = rule_types
+                        .get(pair.as_str())
+                        .and_then(|rule_type| Modifier::of(*rule_type))
+                    else {
+                        continue;
+                    };
+
+                    hints.push(InlayHint {
+                        position,
+                        label: InlayHintLabel::String(format!(": {}", modifier.name)),
+                        kind: Some(InlayHintKind::TYPE),
+                        text_edits: None,
+                        tooltip: Some(InlayHintTooltip::String(modifier.description.to_owned())),
+                        padding_left: None,
+                        padding_right: None,
+                        data: None,
+                    });
+                }
+                Rule::sequence_operator => {
+                    let (Some(skip), Some(tooltip)) = (&skip, &whitespace_tooltip) else {
+                        continue;
+                    };
+
+                    hints.push(InlayHint {
+                        position,
+                        label: InlayHintLabel::String(format!("{skip} ~")),
+                        kind: None,
+                        text_edits: None,
+                        tooltip: Some(InlayHintTooltip::MarkupContent(MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value: tooltip.clone(),
+                        })),
+                        padding_left: Some(true),
+                        padding_right: None,
+                        data: None,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    hints
+}
+
+/// Returns what pest implicitly matches between sequenced tokens, or [None] if the grammar
+/// defines neither `WHITESPACE` nor `COMMENT`, in which case nothing is.
+fn skip_label(rule_types: &HashMap<&str, RuleType>) -> Option<String> {
+    match (
+        rule_types.contains_key("WHITESPACE"),
+        rule_types.contains_key("COMMENT"),
+    ) {
+        (true, true) => Some("(WHITESPACE | COMMENT)*".to_owned()),
+        (true, false) => Some("WHITESPACE*".to_owned()),
+        (false, true) => Some("COMMENT*".to_owned()),
+        (false, false) => None,
+    }
+}
+
+/// Explains the implicit whitespace in a rule, or returns [None] if the rule is atomic, in
+/// which case there is none.
+fn whitespace_tooltip(
+    name: &str,
+    rule_type: &RuleType,
+    atomic_callers: &HashMap<&str, &str>,
+    skip: &str,
+) -> Option<String> {
+    // pest always calls `WHITESPACE` and `COMMENT` atomically.
+    if matches!(rule_type, RuleType::Atomic | RuleType::CompoundAtomic)
+        || name == "WHITESPACE"
+        || name == "COMMENT"
+    {
+        return None;
+    }
+
+    let mut tooltip = format!(
+        "`{name}` is not atomic, so pest implicitly matches `{skip}` between these tokens."
+    );
+    if let Some(caller) = atomic_callers.get(name) {
+        tooltip.push_str(&format!(
+            "\n\nThis does not happen when `{name}` is called from an atomic rule, as it is \
+             from `{caller}`."
+        ));
+    }
+
+    Some(tooltip)
+}
+
+/// Finds the normal and silent rules that are called from an atomic rule, directly or through
+/// other such rules, and so are atomic themselves when called from it. Returns the atomic rule
+/// each one is called from.
+fn atomic_callers<'a>(
+    unit: &[&'a Analysis],
+    rule_types: &HashMap<&str, RuleType>,
+) -> HashMap<&'a str, &'a str> {
+    let mut calls: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (caller, callee) in unit.iter().flat_map(|analysis| analysis.calls()) {
+        calls.entry(caller).or_default().push(callee);
+    }
+
+    let mut queue: VecDeque<(&str, &str)> = unit
+        .iter()
+        .flat_map(|analysis| &analysis.rules)
+        .filter(|(name, ra)| {
+            matches!(ra.rule_type, RuleType::Atomic | RuleType::CompoundAtomic)
+                || *name == "WHITESPACE"
+                || *name == "COMMENT"
+        })
+        .map(|(name, _)| (name.as_str(), name.as_str()))
+        .collect();
+
+    let mut atomic_callers = HashMap::new();
+    while let Some((rule, caller)) = queue.pop_front() {
+        for callee in calls.get(rule).into_iter().flatten() {
+            if matches!(
+                rule_types.get(callee),
+                Some(RuleType::Normal | RuleType::Silent)
+            ) && !atomic_callers.contains_key(callee)
+            {
+                atomic_callers.insert(*callee, caller);
+                queue.push_back((callee, caller));
+            }
+        }
+    }
+
+    atomic_callers
+}
@@ -0,0 +1,203 @@
+use std::{borrow::Cow, iter, ops};
+
+use tower_lsp::lsp_types::{
+    ClientCapabilities, Position, PositionEncodingKind, Range, TextDocumentContentChangeEvent,
+    TextDocumentItem, Url,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The unit the columns of positions are counted in, as negotiated with the client.
+pub enum PositionEncoding {
+    /// Bytes.
+    Utf8,
+    /// UTF-16 code units, which every client supports.
+    #[default]
+    Utf16,
+    /// Characters.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the encoding the client prefers out of those it supports, falling back to UTF-16.
+    pub fn negotiate(capabilities: &ClientCapabilities) -> Self {
+        capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .into_iter()
+            .flatten()
+            .find_map(|kind| match kind.as_str() {
+                "utf-8" => Some(Self::Utf8),
+                "utf-16" => Some(Self::Utf16),
+                "utf-32" => Some(Self::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+            Self::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// Returns the number of columns `text` spans.
+    pub fn columns(self, text: &str) -> u32 {
+        text.chars().map(|c| self.char_len(c)).sum::<usize>() as u32
+    }
+
+    fn char_len(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+}
+
+/// Converts between byte offsets in a text and positions in a [PositionEncoding].
+pub struct LineIndex<'a> {
+    text: &'a str,
+    encoding: PositionEncoding,
+    /// The byte offsets at which the lines of the text start.
+    line_starts: Cow<'a, [usize]>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str, encoding: PositionEncoding) -> Self {
+        Self {
+            text,
+            encoding,
+            line_starts: Cow::Owned(line_starts(text)),
+        }
+    }
+
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding
+    }
+
+    /// Converts a position to a byte offset. Positions past the end of a line are clamped to
+    /// it, as the LSP specification requires, and positions inside a character to its end.
+    pub fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.line_starts.get(position.line as usize) else {
+            return self.text.len();
+        };
+        let end = self
+            .line_starts
+            .get(position.line as usize + 1)
+            .map_or(self.text.len(), |next| next - 1);
+
+        let mut columns = 0;
+        for (index, c) in self.text[start..end].char_indices() {
+            if columns >= position.character as usize {
+                return start + index;
+            }
+            columns += self.encoding.char_len(c);
+        }
+
+        end
+    }
+
+    /// Converts a byte offset to a position.
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.line_starts[line];
+        let character = self.text[start..]
+            .char_indices()
+            .take_while(|(index, _)| start + index < offset)
+            .map(|(_, c)| self.encoding.char_len(c))
+            .sum::<usize>();
+
+        Position::new(line as u32, character as u32)
+    }
+
+    /// Converts a range of byte offsets to a range of positions.
+    pub fn range(&self, range: ops::Range<usize>) -> Range {
+        Range::new(self.position(range.start), self.position(range.end))
+    }
+}
+
+/// Returns the byte offsets at which the lines of `text` start.
+fn line_starts(text: &str) -> Vec<usize> {
+    iter::once(0).chain(line_breaks(text, 0)).collect()
+}
+
+/// Returns the byte offsets at which the lines after the first one of `text` start, if it is
+/// placed at `offset`.
+fn line_breaks(text: &str, offset: usize) -> impl Iterator<Item = usize> {
+    text.match_indices('\n')
+        .map(move |(index, _)| offset + index + 1)
+}
+
+#[derive(Debug)]
+/// A document open in the editor, with the offsets at which its lines start, which are kept up
+/// to date as it is edited, rather than found again for every change and request.
+pub struct Document {
+    item: TextDocumentItem,
+    line_starts: Vec<usize>,
+}
+
+impl Document {
+    pub fn new(item: TextDocumentItem) -> Self {
+        let line_starts = line_starts(&item.text);
+        Self { item, line_starts }
+    }
+
+    /// Returns an index of the document's text, in `encoding`.
+    pub fn index(&self, encoding: PositionEncoding) -> LineIndex<'_> {
+        LineIndex {
+            text: &self.item.text,
+            encoding,
+            line_starts: Cow::Borrowed(&self.line_starts),
+        }
+    }
+
+    pub fn set_uri(&mut self, uri: Url) {
+        self.item.uri = uri;
+    }
+
+    pub fn set_version(&mut self, version: i32) {
+        self.item.version = version;
+    }
+
+    /// Applies a change sent by the client, with positions in `encoding`, to the text.
+    pub fn apply_change(
+        &mut self,
+        change: TextDocumentContentChangeEvent,
+        encoding: PositionEncoding,
+    ) {
+        let Some(range) = change.range else {
+            self.line_starts = line_starts(&change.text);
+            self.item.text = change.text;
+            return;
+        };
+
+        let index = self.index(encoding);
+        let start = index.offset(range.start);
+        let end = index.offset(range.end).max(start);
+
+        // The lines that started in the replaced text are replaced by those that start in the
+        // new text, and the lines after it move by the difference in length.
+        let first = self.line_starts.partition_point(|&line| line <= start);
+        let last = self.line_starts.partition_point(|&line| line <= end);
+        let inserted: Vec<_> = line_breaks(&change.text, start).collect();
+        let moved = first + inserted.len();
+        self.line_starts.splice(first..last, inserted);
+        for line in &mut self.line_starts[moved..] {
+            *line = *line - end + start + change.text.len();
+        }
+
+        self.item.text.replace_range(start..end, &change.text);
+    }
+}
+
+impl ops::Deref for Document {
+    type Target = TextDocumentItem;
+
+    fn deref(&self) -> &TextDocumentItem {
+        &self.item
+    }
+}
@@ -1,47 +1,152 @@
-use std::{collections::HashMap, iter, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, iter, mem,
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use pest::error::Error;
-use pest_meta::parser::{self, Rule};
+use pest::Span;
+use pest_meta::{
+    parser::{self, Rule},
+    validator,
+};
 use serde::Deserialize;
+use serde_json::Value;
 use strum::IntoEnumIterator;
 use tower_lsp::{
     Client, jsonrpc,
     lsp_types::{
+        CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
+        CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
         CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionParams, CodeActionResponse,
-        CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse,
-        ConfigurationItem, DeleteFilesParams, Diagnostic, DiagnosticSeverity,
-        DidChangeConfigurationParams, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
+        Command as LspCommand, CompletionItem, CompletionItemKind, CompletionList,
+        CompletionParams, CompletionResponse, ConfigurationItem, CreateFilesParams,
+        DeleteFilesParams, Diagnostic, DidChangeConfigurationParams, DidChangeTextDocumentParams,
+        DidChangeWatchedFilesParams, DidChangeWatchedFilesRegistrationOptions,
+        DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
         DocumentChanges, DocumentFormattingParams, DocumentSymbolParams, DocumentSymbolResponse,
-        Documentation, Hover, HoverContents, HoverParams, InitializedParams, Location,
-        MarkedString, MarkupContent, MarkupKind, MessageType, OneOf,
-        OptionalVersionedTextDocumentIdentifier, Position, PublishDiagnosticsParams, Range,
-        ReferenceParams, RenameParams, SymbolInformation, SymbolKind, TextDocumentEdit,
-        TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams, TextEdit, Url,
-        VersionedTextDocumentIdentifier, WorkspaceEdit,
+        Documentation, ExecuteCommandParams, FileChangeType, FileSystemWatcher, GlobPattern, Hover,
+        HoverContents, HoverParams, InitializeParams, InitializeResult, InitializedParams,
+        InlayHint, InlayHintParams, Location, MarkedString, MarkupContent, MarkupKind, MessageType,
+        OneOf, OptionalVersionedTextDocumentIdentifier, Position, PrepareRenameResponse,
+        PublishDiagnosticsParams, Range, ReferenceParams, Registration, RenameFilesParams,
+        RenameParams, SemanticTokens, SemanticTokensParams, SemanticTokensRangeParams,
+        SemanticTokensRangeResult, SemanticTokensResult, SymbolInformation, SymbolKind,
+        TextDocumentEdit, TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams,
+        TextEdit, Url, VersionedTextDocumentIdentifier, WorkspaceEdit, WorkspaceSymbolParams,
     },
 };
 
 use crate::{
     analysis::{Analysis, RuleAnalysis},
     builtins::Builtin,
-    helpers::{
-        Diagnostics, Documents, FindWordRange, IntoDiagnostics, IntoRangeWithLine, RangeContains,
-        str_range, validate_pairs,
+    capabilities::capabilities,
+    commands::{ADD_ALWAYS_USED_RULE_NAME, Command},
+    completion::{
+        CompletionContext, EXPRESSION_SNIPPETS, Modifier, RULE_SNIPPETS, Snippet,
+        completion_context, fuzzy_score, header_completions,
     },
+    cycles::Cycles,
+    docs::{self, DocFormat},
+    helpers::{Diagnostics, Documents, RangeContains, find_files, identifier_at, is_grammar},
+    inlay_hints::inlay_hints,
+    line_index::{Document, PositionEncoding},
+    rust::{
+        DerivedParser, GrammarSource, InlineGrammar, RustSource, crate_root, is_rust, rust_files,
+    },
+    sample::{self, SampleAssociation, SampleInputConfig, SampleParsers},
+    semantic_tokens::semantic_tokens,
+    symbols::document_symbols,
+    units::{DocumentAnalysis, analyse_unit, resolve_unit},
 };
 
 #[derive(Deserialize, Default, Debug)]
-#[serde(rename_all = "camelCase")]
+#[serde(default, rename_all = "camelCase")]
 pub struct Config {
     pub always_used_rule_names: Vec<String>,
+    pub sample_inputs: Vec<SampleInputConfig>,
+    /// Sets of grammar files that are combined into a single parser, relative to the
+    /// workspace root.
+    pub grammar_units: Vec<Vec<String>>,
+}
+
+/// The grammars and Rust sources in the workspace roots, as read from disk.
+pub struct WorkspaceIndex {
+    grammars: Documents,
+    rust_sources: HashMap<Url, RustSource>,
+}
+
+impl WorkspaceIndex {
+    /// Reads every grammar and Rust source in `roots`. This blocks, and takes a while in large
+    /// workspaces, so it is done without holding the server.
+    pub fn read(roots: &[PathBuf], encoding: PositionEncoding) -> Self {
+        let grammars = roots
+            .iter()
+            .flat_map(|root| find_files(root, "pest"))
+            .filter_map(|path| {
+                let uri = Url::from_file_path(&path).ok()?;
+                let text = fs::read_to_string(&path).ok()?;
+                Some((
+                    uri.clone(),
+                    TextDocumentItem::new(uri, "pest".to_owned(), 0, text),
+                ))
+            })
+            .collect();
+
+        let rust_sources = roots
+            .iter()
+            .flat_map(|root| rust_files(root))
+            .filter_map(|path| {
+                let uri = Url::from_file_path(&path).ok()?;
+                let text = fs::read_to_string(&path).ok()?;
+                let source = RustSource::new(&uri, &text, encoding);
+                (!source.is_empty()).then_some((uri, source))
+            })
+            .collect();
+
+        Self {
+            grammars,
+            rust_sources,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct PestLanguageServerImpl {
     client: Client,
-    documents: Documents,
+    documents: HashMap<Url, Document>,
+    /// The grammars in the workspace roots, as last read from disk. Open documents take
+    /// precedence over them.
+    workspace_grammars: Documents,
     analyses: HashMap<Url, Analysis>,
     config: Config,
+    workspace_roots: Vec<PathBuf>,
+    /// The tracked grammars, grouped into units as of the last reload.
+    units: Vec<Vec<Url>>,
+    /// The Rust source files that derive parsers or reference rules.
+    rust_sources: HashMap<Url, RustSource>,
+    /// The Rust files each grammar unit could be referenced from as of its last analysis, so
+    /// that it is analysed again when one of them changes, even if it no longer counts.
+    unit_rust_uris: HashMap<Vec<Url>, HashSet<Url>>,
+    /// The diagnostics of each grammar as of its last analysis. Those of inline grammars are
+    /// in their Rust files.
+    diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    /// The input files whose diagnostics are published, with the grammars they were parsed
+    /// with.
+    sample_inputs: HashMap<Url, Url>,
+    /// The documents changed since they were last analysed.
+    pending_changes: HashSet<Url>,
+    /// The number of changes made to documents, to tell whether one is the latest.
+    revision: u64,
+    /// Whether the client accepts snippets in completion items.
+    snippet_support: bool,
+    /// Whether the client can be asked to watch files for changes made outside the editor.
+    watch_support: bool,
+    /// Whether the client can be asked to notify the server of configuration changes.
+    config_change_support: bool,
+    /// The encoding of the positions exchanged with the client.
+    position_encoding: PositionEncoding,
 }
 
 impl PestLanguageServerImpl {
@@ -51,9 +156,61 @@ impl PestLanguageServerImpl {
             client,
             config: Config::default(),
             documents: HashMap::new(),
+            workspace_grammars: HashMap::new(),
+            workspace_roots: Vec::new(),
+            units: Vec::new(),
+            rust_sources: HashMap::new(),
+            unit_rust_uris: HashMap::new(),
+            diagnostics: HashMap::new(),
+            sample_inputs: HashMap::new(),
+            pending_changes: HashSet::new(),
+            revision: 0,
+            snippet_support: false,
+            watch_support: false,
+            config_change_support: false,
+            position_encoding: PositionEncoding::default(),
         }
     }
 
+    pub fn initialize(&mut self, params: InitializeParams) -> InitializeResult {
+        self.position_encoding = PositionEncoding::negotiate(&params.capabilities);
+        self.snippet_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.completion.as_ref())
+            .and_then(|completion| completion.completion_item.as_ref())
+            .and_then(|completion_item| completion_item.snippet_support)
+            .unwrap_or(false);
+        self.watch_support = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|watched_files| watched_files.dynamic_registration)
+            .unwrap_or(false);
+        self.config_change_support = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.did_change_configuration.as_ref())
+            .and_then(|configuration| configuration.dynamic_registration)
+            .unwrap_or(false);
+
+        #[allow(deprecated)]
+        let roots = match params.workspace_folders {
+            Some(folders) => folders.into_iter().map(|folder| folder.uri).collect(),
+            None => params.root_uri.into_iter().collect::<Vec<_>>(),
+        };
+
+        self.workspace_roots = roots
+            .iter()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect();
+
+        capabilities(self.position_encoding)
+    }
+
     async fn try_update_config(&mut self) -> Option<Config> {
         let value = self
             .client
@@ -69,13 +226,16 @@ impl PestLanguageServerImpl {
     }
 
     pub async fn initialized(&mut self, _: InitializedParams) {
-        if self.try_update_config().await.is_none() {
-            self.client
-                .log_message(
-                    MessageType::ERROR,
-                    "Failed to retrieve configuration from client",
-                )
-                .await;
+        match self.try_update_config().await {
+            Some(config) => self.config = config,
+            None => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        "Failed to retrieve configuration from client",
+                    )
+                    .await
+            }
         }
 
         self.client
@@ -84,6 +244,77 @@ impl PestLanguageServerImpl {
                 format!("Pest Language Server v{}", env!("CARGO_PKG_VERSION")),
             )
             .await;
+
+        self.register_capabilities().await;
+    }
+
+    /// Returns a function that reads the workspace, for [Self::index_workspace].
+    pub fn workspace_reader(&self) -> impl FnOnce() -> WorkspaceIndex + Send + 'static {
+        let roots = self.workspace_roots.clone();
+        let encoding = self.position_encoding;
+        move || WorkspaceIndex::read(&roots, encoding)
+    }
+
+    /// Adds every grammar and Rust source in the workspace, whether or not it is open, so that
+    /// references and unused rules are found across the whole workspace, and analyses them.
+    /// Files opened or changed while the workspace was read are more recent, and are kept.
+    pub async fn index_workspace(&mut self, index: WorkspaceIndex) {
+        for (uri, document) in index.grammars {
+            self.workspace_grammars.entry(uri).or_insert(document);
+        }
+        for (uri, source) in index.rust_sources {
+            self.rust_sources.entry(uri).or_insert(source);
+        }
+
+        let diagnostics = self.reload().await;
+        self.send_diagnostics(diagnostics).await;
+    }
+
+    /// Asks the client to report changes to grammars and Rust files made outside the editor,
+    /// e.g. by version control, so the workspace index stays up to date, and changes to the
+    /// configuration, e.g. by the quick fix that adds an always used rule name, so they take
+    /// effect.
+    async fn register_capabilities(&self) {
+        let mut registrations = Vec::new();
+        if self.watch_support {
+            let options = DidChangeWatchedFilesRegistrationOptions {
+                watchers: ["**/*.pest", "**/*.rs"]
+                    .into_iter()
+                    .map(|glob| FileSystemWatcher {
+                        glob_pattern: GlobPattern::String(glob.to_string()),
+                        kind: None,
+                    })
+                    .collect(),
+            };
+            registrations.push(Registration {
+                id: "pest-watched-files".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            });
+        }
+
+        if self.config_change_support {
+            registrations.push(Registration {
+                id: "pest-configuration".to_string(),
+                method: "workspace/didChangeConfiguration".to_string(),
+                register_options: Some(serde_json::json!({ "section": "pestIdeTools" })),
+            });
+        }
+
+        if !registrations.is_empty()
+            && self
+                .client
+                .register_capability(registrations)
+                .await
+                .is_err()
+        {
+            self.client
+                .log_message(
+                    MessageType::ERROR,
+                    "Failed to register for workspace changes",
+                )
+                .await;
+        }
     }
 
     pub async fn shutdown(&self) -> jsonrpc::Result<()> {
@@ -94,8 +325,32 @@ impl PestLanguageServerImpl {
     }
 
     pub async fn did_change_configuration(&mut self, params: DidChangeConfigurationParams) {
-        let Ok(config) = serde_json::from_value(params.settings) else {
-            return;
+        let settings = match params.settings {
+            Value::Object(mut settings) if settings.contains_key("pestIdeTools") => {
+                settings.remove("pestIdeTools").unwrap_or_default()
+            }
+            settings => settings,
+        };
+
+        // Clients that expect the configuration to be pulled send no settings with the change.
+        let pushed = match settings {
+            Value::Null => None,
+            settings => serde_json::from_value(settings).ok(),
+        };
+        let config = match pushed {
+            Some(config) => config,
+            None => {
+                let Some(config) = self.try_update_config().await else {
+                    self.client
+                        .log_message(
+                            MessageType::ERROR,
+                            "Failed to retrieve configuration from client",
+                        )
+                        .await;
+                    return;
+                };
+                config
+            }
         };
 
         self.config = config;
@@ -112,9 +367,10 @@ impl PestLanguageServerImpl {
 
     pub async fn did_open(&mut self, params: DidOpenTextDocumentParams) {
         let DidOpenTextDocumentParams { text_document } = params;
+        let uri = text_document.uri.clone();
         if self
             .documents
-            .insert(text_document.uri.clone(), text_document)
+            .insert(uri.clone(), Document::new(text_document))
             .is_some()
         {
             self.client
@@ -125,43 +381,131 @@ impl PestLanguageServerImpl {
                 .await;
         }
 
-        let diagnostics = self.reload().await;
+        self.update_rust_source(&uri);
+
+        let diagnostics = self.reload_changed(&[uri]).await;
         self.send_diagnostics(diagnostics).await;
     }
 
-    pub async fn did_change(&mut self, params: DidChangeTextDocumentParams) {
+    /// Applies changes to a document, without analysing it. Returns the revision of the change,
+    /// to pass to [Self::analyse_changes] once the client stops typing.
+    pub async fn did_change(&mut self, params: DidChangeTextDocumentParams) -> Option<u64> {
         let DidChangeTextDocumentParams {
             text_document,
             content_changes,
         } = params;
         let VersionedTextDocumentIdentifier { uri, version } = text_document;
 
-        let Some(change) = content_changes.into_iter().next_back() else {
+        if content_changes.is_empty() {
             self.client
                 .log_message(MessageType::ERROR, "Editor returned empty change vector")
                 .await;
-            return;
-        };
+            return None;
+        }
 
-        let updated_doc =
-            TextDocumentItem::new(uri.clone(), "pest".to_owned(), version, change.text);
         let Some(document) = self.documents.get_mut(&uri) else {
             self.client
                 .log_message(MessageType::ERROR, "Editor changed nonexistent document")
                 .await;
-            return;
+            return None;
         };
 
-        *document = updated_doc;
-        let diagnostics = self.reload().await;
+        document.set_version(version);
+        // The changes are relative to the text as left by the previous one.
+        for change in content_changes {
+            document.apply_change(change, self.position_encoding);
+        }
+
+        Some(self.record_change(uri))
+    }
+
+    pub async fn did_close(&mut self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        if self.documents.remove(&uri).is_none() {
+            self.client
+                .log_message(MessageType::ERROR, "Editor closed untracked document")
+                .await;
+            return;
+        }
+
+        self.forget_document(&uri).await;
+        let diagnostics = self.reload_changed(&[uri]).await;
+        self.send_diagnostics(diagnostics).await;
+    }
+
+    /// Analyses the pending changes right away, as saving is a natural pause in typing.
+    pub async fn did_save(&mut self, _: DidSaveTextDocumentParams) {
+        self.analyse_pending().await;
+    }
+
+    pub async fn did_create_files(&mut self, params: CreateFilesParams) {
+        let mut created = Vec::new();
+        for file in params.files {
+            match Url::parse(&file.uri) {
+                Ok(uri) => {
+                    self.index_grammar(&uri);
+                    created.push(uri);
+                }
+                Err(e) => {
+                    self.client
+                        .log_message(MessageType::ERROR, format!("Failed to parse URI {e}"))
+                        .await
+                }
+            }
+        }
+
+        let diagnostics = self.reload_changed(&created).await;
+        self.send_diagnostics(diagnostics).await;
+    }
+
+    /// Moves everything known about renamed documents to their new URIs, and analyses the
+    /// units they are in again, as their paths may resolve differently.
+    pub async fn did_rename_files(&mut self, params: RenameFilesParams) {
+        let mut renamed = Vec::new();
+        for file in params.files {
+            let (old_uri, new_uri) = match (Url::parse(&file.old_uri), Url::parse(&file.new_uri)) {
+                (Ok(old_uri), Ok(new_uri)) => (old_uri, new_uri),
+                (Err(e), _) | (_, Err(e)) => {
+                    self.client
+                        .log_message(MessageType::ERROR, format!("Failed to parse URI {e}"))
+                        .await;
+                    continue;
+                }
+            };
+
+            if let Some(mut document) = self.documents.remove(&old_uri) {
+                document.set_uri(new_uri.clone());
+                self.documents.insert(new_uri.clone(), document);
+            }
+            if let Some(analysis) = self.analyses.remove(&old_uri) {
+                self.analyses.insert(new_uri.clone(), analysis);
+            }
+            if let Some(diagnostics) = self.diagnostics.remove(&old_uri) {
+                self.diagnostics.insert(new_uri.clone(), diagnostics);
+            }
+            if self.pending_changes.remove(&old_uri) {
+                self.pending_changes.insert(new_uri.clone());
+            }
+
+            self.forget_document(&old_uri).await;
+            self.index_grammar(&new_uri);
+            self.update_rust_source(&new_uri);
+            renamed.extend([old_uri, new_uri]);
+        }
+
+        let diagnostics = self.reload_changed(&renamed).await;
         self.send_diagnostics(diagnostics).await;
     }
 
     pub async fn did_delete_files(&mut self, params: DeleteFilesParams) {
-        let files = params.files;
-        for file in files {
+        let mut deleted = Vec::new();
+        for file in params.files {
             match Url::parse(&file.uri) {
-                Ok(uri) => _ = self.documents.remove(&uri),
+                Ok(uri) => {
+                    self.documents.remove(&uri);
+                    self.forget_document(&uri).await;
+                    deleted.push(uri);
+                }
                 Err(e) => {
                     self.client
                         .log_message(MessageType::ERROR, format!("Failed to parse URI {e}"))
@@ -170,7 +514,26 @@ impl PestLanguageServerImpl {
             }
         }
 
-        let diagnostics = self.reload().await;
+        let diagnostics = self.reload_changed(&deleted).await;
+        self.send_diagnostics(diagnostics).await;
+    }
+
+    /// Reads grammars and Rust files changed outside the editor from disk again. The editor's
+    /// copies of open documents still take precedence.
+    pub async fn did_change_watched_files(&mut self, params: DidChangeWatchedFilesParams) {
+        let mut changed = Vec::new();
+        for change in params.changes {
+            if change.typ == FileChangeType::DELETED && !self.documents.contains_key(&change.uri) {
+                self.forget_document(&change.uri).await;
+            } else {
+                self.index_grammar(&change.uri);
+                self.update_rust_source(&change.uri);
+            }
+
+            changed.push(change.uri);
+        }
+
+        let diagnostics = self.reload_changed(&changed).await;
         self.send_diagnostics(diagnostics).await;
     }
 
@@ -183,12 +546,15 @@ impl PestLanguageServerImpl {
         } = params;
         let only = context.only.as_ref();
         let Some(analysis) = self.analyses.get(&uri) else {
-            self.client
-                .log_message(
-                    MessageType::ERROR,
-                    "Editor requested code action on untracked document",
-                )
-                .await;
+            // Sample inputs and grammars that fail to parse are tracked, but have no actions.
+            if self.grammar_document(&uri).is_none() {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        "Editor requested code action on untracked document",
+                    )
+                    .await;
+            }
             return Vec::new();
         };
 
@@ -205,17 +571,127 @@ impl PestLanguageServerImpl {
 
         let inline = only
             .is_none_or(|only| only.contains(&CodeActionKind::REFACTOR_INLINE))
-            .then(|| self.refactor_inline(uri, analysis, range))
+            .then(|| self.refactor_inline(uri.clone(), analysis, range))
             .flatten();
 
-        inline_all
+        let quick_fixes = only
+            .is_none_or(|only| only.contains(&CodeActionKind::QUICKFIX))
+            .then(|| {
+                context.diagnostics.iter().flat_map(|diagnostic| {
+                    self.quick_fix_undefined_rule(uri.clone(), analysis, diagnostic)
+                        .into_iter()
+                        .chain(self.quick_fixes_unused_rule(&uri, analysis, diagnostic))
+                })
+            })
             .into_iter()
+            .flatten();
+
+        quick_fixes
+            .chain(inline_all)
             .chain(extract)
             .chain(inline)
             .map(CodeActionOrCommand::CodeAction)
             .collect()
     }
 
+    fn quick_fix_undefined_rule(
+        &self,
+        uri: Url,
+        analysis: &Analysis,
+        diagnostic: &Diagnostic,
+    ) -> Option<CodeAction> {
+        let name = diagnostic
+            .message
+            .strip_prefix("rule ")?
+            .strip_suffix(" is undefined")?;
+        let (_, caller) = analysis.rule_containing(diagnostic.range)?;
+
+        let pos = caller.definition_location.end;
+        let edit = vec![TextEdit {
+            range: Range {
+                start: pos,
+                end: pos,
+            },
+            new_text: format!("\n\n{name} = {{ /* TODO */ ANY }}"),
+        }];
+
+        let changes = HashMap::from_iter(iter::once((uri, edit)));
+
+        let edit = Some(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        });
+
+        Some(CodeAction {
+            title: format!("Create rule {name}"),
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            edit,
+            is_preferred: Some(true),
+            ..Default::default()
+        })
+    }
+
+    fn quick_fixes_unused_rule(
+        &self,
+        uri: &Url,
+        analysis: &Analysis,
+        diagnostic: &Diagnostic,
+    ) -> Vec<CodeAction> {
+        let Some((name, ra)) = diagnostic
+            .message
+            .strip_prefix("Rule ")
+            .and_then(|message| message.strip_suffix(" is unused"))
+            .and_then(|name| analysis.rules.get_key_value(name))
+        else {
+            return Vec::new();
+        };
+
+        let quick_fix = |title: String, edits: Vec<TextEdit>| CodeAction {
+            title,
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            edit: Some(WorkspaceEdit {
+                changes: Some(HashMap::from_iter(iter::once((uri.clone(), edits)))),
+                document_changes: None,
+                change_annotations: None,
+            }),
+            ..Default::default()
+        };
+
+        // Remove the line break after the rule, along with its documentation.
+        let start = ra.doc_location.unwrap_or(ra.definition_location).start;
+        let end = Position::new(ra.definition_location.end.line + 1, 0);
+        let mut actions = vec![quick_fix(
+            format!("Delete rule {name}"),
+            vec![TextEdit::new(Range::new(start, end), String::new())],
+        )];
+
+        let prefixed = format!("_{name}");
+        if self.rule_definition(uri, &prefixed).is_none() {
+            let start = ra.identifier_location.start;
+            actions.push(quick_fix(
+                format!("Rename to {prefixed}"),
+                vec![TextEdit::new(Range::new(start, start), "_".to_owned())],
+            ));
+        }
+
+        actions.push(CodeAction {
+            title: format!("Add {name} to always used rule names"),
+            kind: Some(CodeActionKind::QUICKFIX),
+            diagnostics: Some(vec![diagnostic.clone()]),
+            command: Some(LspCommand::new(
+                format!("Add {name} to always used rule names"),
+                ADD_ALWAYS_USED_RULE_NAME.to_owned(),
+                Some(vec![Value::String(name.to_owned())]),
+            )),
+            ..Default::default()
+        });
+
+        actions
+    }
+
     fn refactor_inline(&self, uri: Url, analysis: &Analysis, range: Range) -> Option<CodeAction> {
         let ((name, ra), reference) = analysis.rules.iter().find_map(|pair @ (_, ra)| {
             Some((
@@ -363,42 +839,123 @@ impl PestLanguageServerImpl {
             ..
         } = params;
 
-        let document = &self.documents[&text_document_position.text_document.uri];
-        let mut lines = document.text.lines();
-        let line = lines
-            .nth(text_document_position.position.line as usize)
-            .unwrap_or("");
-        let range = line.word_range_at_idx(text_document_position.position.character as usize);
-        let partial_identifier = &str_range(line, &range);
+        let uri = &text_document_position.text_document.uri;
+        let position = text_document_position.position;
+        let document = self.open_grammar(uri)?;
+        let offset = document.index(self.position_encoding).offset(position);
 
-        let analysis = self.analyses.get(&document.uri)?;
-        let rule_completions = analysis
-            .rules
+        let context = completion_context(&document.text, offset);
+        let (partial_identifier, snippets): (_, &[Snippet]) = match context {
+            CompletionContext::Expression(partial_identifier) => {
+                (partial_identifier, &EXPRESSION_SNIPPETS)
+            }
+            CompletionContext::Rule(partial_identifier) => (partial_identifier, &RULE_SNIPPETS),
+            CompletionContext::Modifier => {
+                return Some(CompletionResponse::Array(header_completions(
+                    position, true,
+                )));
+            }
+            CompletionContext::Body => {
+                return Some(CompletionResponse::Array(header_completions(
+                    position, false,
+                )));
+            }
+            CompletionContext::None => return None,
+        };
+
+        // A new rule's name is not a reference, so only snippets are offered for it.
+        let is_expression = matches!(context, CompletionContext::Expression(_));
+        let rule_completions = self
+            .unit_of(uri)
             .iter()
-            .filter(|(name, _)| name.starts_with(partial_identifier))
-            .map(|(name, ra)| CompletionItem {
-                label: name.to_owned(),
-                kind: Some(CompletionItemKind::FIELD),
-                documentation: ra
-                    .doc
-                    .clone()
-                    .map(|value| MarkupContent {
-                        kind: MarkupKind::Markdown,
-                        value,
-                    })
-                    .map(Documentation::MarkupContent),
-                ..Default::default()
+            .filter(|_| is_expression)
+            .filter_map(|unit_uri| Some((unit_uri, self.analyses.get(unit_uri)?)))
+            .flat_map(|(unit_uri, analysis)| {
+                analysis
+                    .rules
+                    .iter()
+                    .map(move |rule| (unit_uri, analysis, rule))
+            })
+            .filter_map(|(unit_uri, analysis, (name, ra))| {
+                let score = fuzzy_score(partial_identifier, name)?;
+
+                // Rules from other documents of the unit are also described by their grammar.
+                let grammar_doc = analysis.doc.as_ref().filter(|_| unit_uri != uri);
+                let documentation = match (&ra.doc, grammar_doc) {
+                    (Some(doc), Some(grammar_doc)) => {
+                        Some(format!("{doc}\n\n---\n\n{grammar_doc}"))
+                    }
+                    (doc, grammar_doc) => doc.as_ref().or(grammar_doc).cloned(),
+                };
+
+                let item = CompletionItem {
+                    label: name.to_owned(),
+                    kind: Some(CompletionItemKind::FIELD),
+                    documentation: documentation
+                        .map(|value| MarkupContent {
+                            kind: MarkupKind::Markdown,
+                            value,
+                        })
+                        .map(Documentation::MarkupContent),
+                    ..Default::default()
+                };
+                Some((score, 0, item))
             });
 
-        let builtins_completions = Builtin::iter().map(|builtin| CompletionItem {
-            label: builtin.as_ref().to_string(),
-            kind: Some(builtin.kind()),
-            documentation: Some(Documentation::String(builtin.description().to_string())),
-            ..Default::default()
+        let builtins_completions =
+            Builtin::iter()
+                .filter(|_| is_expression)
+                .filter_map(|builtin| {
+                    let score = fuzzy_score(partial_identifier, builtin.as_ref())?;
+                    let item = CompletionItem {
+                        label: builtin.as_ref().to_string(),
+                        kind: Some(builtin.kind()),
+                        documentation: Some(Documentation::String(
+                            builtin.description().to_string(),
+                        )),
+                        ..Default::default()
+                    };
+                    Some((score, 1, item))
+                });
+
+        let snippet_completions =
+            snippets
+                .iter()
+                .filter(|_| self.snippet_support)
+                .filter_map(|snippet| {
+                    let score = fuzzy_score(partial_identifier, snippet.label)?;
+                    Some((score, 2, snippet.completion_item()))
+                });
+
+        let mut rule_completions: Vec<_> = rule_completions.collect();
+        rule_completions.sort_by(|(_, _, a), (_, _, b)| a.label.cmp(&b.label));
+
+        // Rules come before builtins with the same score, and builtins before snippets. The sort
+        // is stable, so builtins and snippets otherwise stay in the order they are declared.
+        let mut completions: Vec<_> = rule_completions
+            .into_iter()
+            .chain(builtins_completions)
+            .chain(snippet_completions)
+            .collect();
+        completions.sort_by(|(a_score, a_group, _), (b_score, b_group, _)| {
+            (b_score, a_group).cmp(&(a_score, b_group))
         });
 
-        let completions = rule_completions.chain(builtins_completions).collect();
-        Some(CompletionResponse::Array(completions))
+        // The items are ranked here, so the filter text stops the client from re-ranking them,
+        // and the list is incomplete so that it is requested again as the identifier is typed.
+        let items = completions
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_, _, item))| CompletionItem {
+                sort_text: Some(format!("{index:04}")),
+                filter_text: Some(partial_identifier.to_owned()),
+                ..item
+            })
+            .collect();
+        Some(CompletionResponse::List(CompletionList {
+            is_incomplete: true,
+            items,
+        }))
     }
 
     pub fn hover(&self, params: HoverParams) -> Option<Hover> {
@@ -406,97 +963,242 @@ impl PestLanguageServerImpl {
             text_document_position_params,
             ..
         } = params;
-        let document = &self.documents[&text_document_position_params.text_document.uri];
+        let document = self.open_grammar(&text_document_position_params.text_document.uri)?;
+        let position = text_document_position_params.position;
+        let (identifier, range) = self.identifier_at(document, position);
 
-        let mut lines = document.text.lines();
-        let line = lines
-            .nth(text_document_position_params.position.line as usize)
-            .unwrap_or("");
-        let range =
-            line.word_range_at_idx(text_document_position_params.position.character as usize);
-        let identifier = &str_range(line, &range);
+        let grammar_doc = self.analyses.get(&document.uri).and_then(|analysis| {
+            let doc = analysis.doc.clone()?;
+            Some((doc, analysis.doc_location?))
+        });
+        if let Some((doc, doc_location)) = &grammar_doc
+            && doc_location.contains(Range::new(position, position))
+        {
+            return Some(grammar_doc_hover(doc.clone(), *doc_location));
+        }
 
         if let Ok(builtin) = Builtin::from_str(identifier) {
             let contents =
                 HoverContents::Scalar(MarkedString::String(builtin.description().to_owned()));
-            let range = Some(range.into_range(text_document_position_params.position.line));
-            let hover = Hover { contents, range };
+            let hover = Hover {
+                contents,
+                range: Some(range),
+            };
             return Some(hover);
         }
 
-        let ra = self
-            .analyses
-            .get(&document.uri)?
-            .rules
+        let Some((_, ra)) = self.rule_definition(&document.uri, identifier) else {
+            // The first line of a grammar stands for the grammar as a whole.
+            let (doc, _) = grammar_doc.filter(|_| position.line == 0)?;
+            return Some(grammar_doc_hover(doc, range));
+        };
+
+        let contents = HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: self.rule_hover(&document.uri, identifier, ra),
+        });
+        Some(Hover {
+            contents,
+            range: Some(range),
+        })
+    }
+
+    /// Describes a rule: its definition and documentation, followed by its modifier, how many
+    /// references it has, and whether it can match empty input.
+    fn rule_hover(&self, uri: &Url, rule_name: &str, ra: &RuleAnalysis) -> String {
+        let modifier = Modifier::of(ra.rule_type);
+        let mut hover = format!(
+            "```pest\n{rule_name} = {}{{ {} }}\n```",
+            modifier.map_or("", |modifier| modifier.symbol),
+            ra.expression.trim()
+        );
+        if let Some(doc) = &ra.doc {
+            hover.push_str("\n\n");
+            hover.push_str(doc);
+        }
+
+        let mut details = Vec::new();
+        details.extend(modifier.map(|modifier| modifier.description.to_owned()));
+
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        let references = self.rule_locations(uri, rule_name).len().saturating_sub(1);
+        let rust_references = self.rust_references(uri, rule_name).len();
+        details.push(match rust_references {
+            0 => format!("{references} reference{}", plural(references)),
+            _ => format!(
+                "{} reference{}, {rust_references} in Rust code",
+                references + rust_references,
+                plural(references + rust_references)
+            ),
+        });
+
+        match self.empty_match_trace(uri, rule_name) {
+            Some(Some(trace)) if trace.len() > 1 => details.push(format!(
+                "Can match empty input, through {}",
+                trace[1..]
+                    .iter()
+                    .map(|rule| format!("`{rule}`"))
+                    .collect::<Vec<_>>()
+                    .join(" → ")
+            )),
+            Some(Some(_)) => details.push("Can match empty input".to_owned()),
+            Some(None) => details.push("Always consumes input".to_owned()),
+            None => {}
+        }
+
+        hover.push_str("\n\n---\n\n");
+        hover.push_str(&details.join("\n\n"));
+        hover
+    }
+
+    /// Returns the chain of rules through which a rule can match empty input, starting with the
+    /// rule itself, or [None] if it always consumes input. Returns [None] at the outer level if
+    /// the grammar unit of `uri` does not parse, and so cannot be checked.
+    fn empty_match_trace(&self, uri: &Url, rule_name: &str) -> Option<Option<Vec<String>>> {
+        // The documents of the unit are joined as they are for validation.
+        let text: String = self
+            .unit_of(uri)
             .iter()
-            .find(|(name, _)| *name == identifier)?
-            .1;
+            .filter_map(|uri| self.grammar_document(uri))
+            .flat_map(|document| [document.text.as_str(), "\n"])
+            .collect();
+        let pairs = parser::parse(Rule::grammar_rules, &text).ok()?;
 
-        let contents = HoverContents::Scalar(MarkedString::String(ra.doc.clone()?));
-        let range = Some(range.into_range(text_document_position_params.position.line));
-        Some(Hover { contents, range })
+        let trace = Cycles::new(pairs).empty_match_trace(rule_name);
+        Some(trace.map(|trace| trace.into_iter().map(str::to_owned).collect()))
     }
 
-    pub fn rename(&self, params: RenameParams) -> WorkspaceEdit {
+    pub fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> jsonrpc::Result<Option<PrepareRenameResponse>> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params;
+
+        let Some(document) = self.open_grammar(&text_document.uri) else {
+            return Ok(None);
+        };
+        let (identifier, range) = self.identifier_at(document, position);
+
+        self.check_renamed_rule(&document.uri, identifier)?;
+        Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+            range,
+            placeholder: identifier.to_owned(),
+        }))
+    }
+
+    pub fn rename(&self, params: RenameParams) -> jsonrpc::Result<Option<WorkspaceEdit>> {
         let RenameParams {
             text_document_position,
             new_name,
             ..
         } = params;
 
-        let document = &self.documents[&text_document_position.text_document.uri];
-        let line = document
-            .text
-            .lines()
-            .nth(text_document_position.position.line as usize)
-            .unwrap_or("");
-        let old_identifier = &str_range(
-            line,
-            &line.word_range_at_idx(text_document_position.position.character as usize),
-        );
+        let Some(document) = self.open_grammar(&text_document_position.text_document.uri) else {
+            return Ok(None);
+        };
+        let (old_identifier, _) = self.identifier_at(document, text_document_position.position);
 
-        let edits = self
-            .rule_analysis(&document.uri, old_identifier)
-            .into_iter()
-            .flat_map(|ra| ra.references_and_identifier())
-            .map(|range| TextEdit {
+        self.check_renamed_rule(&document.uri, old_identifier)?;
+        self.check_new_rule_name(&document.uri, old_identifier, &new_name)?;
+
+        let mut edits: Vec<TextDocumentEdit> = Vec::new();
+        let mut locations = self.rule_locations(&document.uri, old_identifier);
+        if !locations.is_empty() {
+            locations.extend(self.rust_references(&document.uri, old_identifier));
+        }
+
+        for Location { uri, range } in locations {
+            let edit = OneOf::Left(TextEdit {
                 range,
                 new_text: new_name.clone(),
-            })
-            .map(OneOf::Left)
-            .collect();
-
-        let text_document = OptionalVersionedTextDocumentIdentifier {
-            uri: text_document_position.text_document.uri,
-            version: Some(document.version),
-        };
+            });
 
-        let edit = TextDocumentEdit {
-            text_document,
-            edits,
-        };
+            match edits.iter_mut().find(|edit| edit.text_document.uri == uri) {
+                Some(document_edit) => document_edit.edits.push(edit),
+                None => edits.push(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier {
+                        version: self.documents.get(&uri).map(|document| document.version),
+                        uri,
+                    },
+                    edits: vec![edit],
+                }),
+            }
+        }
 
-        let document_changes = Some(DocumentChanges::Edits(vec![edit]));
+        let document_changes = Some(DocumentChanges::Edits(edits));
 
-        WorkspaceEdit {
+        Ok(Some(WorkspaceEdit {
             change_annotations: None,
             changes: None,
             document_changes,
+        }))
+    }
+
+    /// Checks that `identifier` is a rule defined in the grammar unit of `uri`, and so can be
+    /// renamed.
+    fn check_renamed_rule(&self, uri: &Url, identifier: &str) -> jsonrpc::Result<()> {
+        if Builtin::from_str(identifier).is_ok() {
+            return Err(request_failed(format!(
+                "{identifier} is a builtin rule, and cannot be renamed"
+            )));
         }
+
+        if self.rule_definition(uri, identifier).is_none() {
+            return Err(request_failed(
+                "Only rules defined in this grammar can be renamed".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that renaming `old_name` to `new_name` produces a valid grammar, without
+    /// duplicate rules.
+    fn check_new_rule_name(
+        &self,
+        uri: &Url,
+        old_name: &str,
+        new_name: &str,
+    ) -> jsonrpc::Result<()> {
+        let is_identifier =
+            parser::parse(Rule::identifier, new_name).is_ok_and(|pairs| pairs.as_str() == new_name);
+        if !is_identifier {
+            return Err(request_failed(format!(
+                "{new_name} is not a valid rule name"
+            )));
+        }
+
+        let definitions = vec![Span::new(new_name, 0, new_name.len()).expect("span of whole name")];
+        if let Some(error) = validator::validate_pest_keywords(&definitions).first() {
+            return Err(request_failed(error.variant.message().into_owned()));
+        }
+
+        if Builtin::from_str(new_name).is_ok() {
+            return Err(request_failed(format!("{new_name} is a builtin rule")));
+        }
+
+        if new_name != old_name && self.rule_definition(uri, new_name).is_some() {
+            return Err(request_failed(format!(
+                "A rule named {new_name} already exists"
+            )));
+        }
+
+        Ok(())
     }
 
     pub fn goto_definition(&self, params: TextDocumentPositionParams) -> Option<Location> {
         let uri = params.text_document.uri;
-        let document = &self.documents[&uri];
-        let mut lines = document.text.lines();
-        let line = lines.nth(params.position.line as usize).unwrap_or("");
-        let range = line.word_range_at_idx(params.position.character as usize);
-        let identifier = &str_range(line, &range);
+        let document = self.open_grammar(&uri)?;
+        let (identifier, _) = self.identifier_at(document, params.position);
 
-        let range = self
-            .rule_analysis(&document.uri, identifier)?
-            .definition_location;
-        Some(Location { uri, range })
+        let (uri, ra) = self.rule_definition(&uri, identifier)?;
+        Some(Location {
+            uri: uri.clone(),
+            range: ra.definition_location,
+        })
     }
 
     pub fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
@@ -506,124 +1208,850 @@ impl PestLanguageServerImpl {
         } = params;
 
         let uri = text_document_position.text_document.uri;
-        let document = &self.documents[&uri];
-
-        let mut lines = document.text.lines();
-        let line = lines
-            .nth(text_document_position.position.line as usize)
-            .unwrap_or("");
-        let range = line.word_range_at_idx(text_document_position.position.character as usize);
-        let identifier = &str_range(line, &range);
-
-        let rule_analysis = self.rule_analysis(&document.uri, identifier)?;
-        let locations = rule_analysis
-            .references_and_identifier()
-            .map(|range| Location {
-                uri: uri.clone(),
-                range,
+        let document = self.open_grammar(&uri)?;
+        let (identifier, _) = self.identifier_at(document, text_document_position.position);
+
+        let mut locations = self.rule_locations(&document.uri, identifier);
+        if locations.is_empty() {
+            return None;
+        }
+
+        locations.extend(self.rust_references(&document.uri, identifier));
+        Some(locations)
+    }
+
+    pub fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Option<Vec<CallHierarchyItem>> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+
+        let document = self.open_grammar(&text_document.uri)?;
+        let (identifier, _) = self.identifier_at(document, position);
+
+        let (uri, ra) = self.rule_definition(&document.uri, identifier)?;
+        Some(vec![call_hierarchy_item(uri.clone(), identifier, ra)])
+    }
+
+    pub fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Option<Vec<CallHierarchyIncomingCall>> {
+        let CallHierarchyItem { name, uri, .. } = params.item;
+        self.rule_analysis(&uri, &name)?;
+
+        // Callers can be in any document of the unit, and are listed document by document.
+        let mut calls = Vec::new();
+        for caller_uri in self.unit_of(&uri) {
+            let Some(analysis) = self.analyses.get(caller_uri) else {
+                continue;
+            };
+
+            let mut callers: Vec<((&str, &RuleAnalysis), Vec<Range>)> = Vec::new();
+            for reference in analysis.identifiers.get(&name).into_iter().flatten() {
+                let Some(caller @ (caller_name, _)) = analysis.rule_containing(*reference) else {
+                    continue;
+                };
+
+                match callers
+                    .iter_mut()
+                    .find(|((name, _), _)| *name == caller_name)
+                {
+                    Some((_, from_ranges)) => from_ranges.push(*reference),
+                    None => callers.push((caller, vec![*reference])),
+                }
+            }
+
+            callers.sort_by_key(|((_, ra), _)| ra.definition_location.start);
+            calls.extend(callers.into_iter().map(|((name, ra), from_ranges)| {
+                CallHierarchyIncomingCall {
+                    from: call_hierarchy_item(caller_uri.clone(), name, ra),
+                    from_ranges,
+                }
+            }));
+        }
+
+        Some(calls)
+    }
+
+    pub fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Option<Vec<CallHierarchyOutgoingCall>> {
+        let CallHierarchyItem { name, uri, .. } = params.item;
+        let analysis = self.analyses.get(&uri)?;
+        let caller = analysis.rules.get(&name)?;
+
+        // Callees can be defined in any document of the unit.
+        let mut calls: Vec<_> = analysis
+            .identifiers
+            .iter()
+            .filter_map(|(callee, ranges)| {
+                let (callee_uri, ra) = self.rule_definition(&uri, callee)?;
+                let from_ranges: Vec<_> = ranges
+                    .iter()
+                    .copied()
+                    .filter(|reference| caller.definition_location.contains(*reference))
+                    .collect();
+
+                (!from_ranges.is_empty()).then(|| CallHierarchyOutgoingCall {
+                    to: call_hierarchy_item(callee_uri.clone(), callee, ra),
+                    from_ranges,
+                })
             })
             .collect();
-        Some(locations)
+
+        calls.sort_by_key(|call| call.from_ranges[0].start);
+        Some(calls)
     }
 
     pub fn formatting(&self, params: DocumentFormattingParams) -> Option<Vec<TextEdit>> {
         let DocumentFormattingParams { text_document, .. } = params;
 
-        let document = &self.documents[&text_document.uri];
+        let document = self.open_grammar(&text_document.uri)?;
         let input = document.text.as_str();
 
         let fmt = pest_fmt::Formatter::new(input);
         let formatted = fmt.format().ok()?;
-        let lines = document.text.lines();
-        let last_line = lines.clone().last().unwrap_or("");
-        let end = Position::new(lines.count() as u32, last_line.len() as u32);
+        let end = document.index(self.position_encoding).position(input.len());
         let range = Range::new(Position::new(0, 0), end);
         Some(vec![TextEdit::new(range, formatted)])
     }
 
-    #[allow(deprecated)]
     pub fn document_symbol(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
         let uri = params.text_document.uri;
         let analysis = self.analyses.get(&uri)?;
-        Some(DocumentSymbolResponse::Flat(
-            analysis
-                .rules
-                .iter()
-                .map(|(name, ra)| SymbolInformation {
-                    name: name.to_owned(),
-                    kind: SymbolKind::FIELD,
-                    tags: None,
-                    // Stupid library forces me to specify a deprecated field like what
-                    deprecated: None,
-                    location: Location {
-                        uri: uri.clone(),
-                        range: ra.identifier_location,
-                    },
-                    container_name: None,
-                })
-                .collect(),
-        ))
+        let document = self.documents.get(&uri);
+        let index = document.map(|document| document.index(self.position_encoding));
+        let pairs = document
+            .and_then(|document| parser::parse(Rule::grammar_rules, &document.text).ok())
+            .zip(index.as_ref());
+
+        Some(DocumentSymbolResponse::Nested(document_symbols(
+            analysis, pairs,
+        )))
     }
 
-    fn analyse_document(
-        config: &Config,
-        document: &TextDocumentItem,
-        capacity: Option<usize>,
-    ) -> Result<(Analysis, Vec<Diagnostic>), Vec<Error<Rule>>> {
-        let pairs =
-            parser::parse(Rule::grammar_rules, document.text.as_str()).map_err(|err| vec![err])?;
-
-        let analysis = Analysis::new(pairs.clone(), capacity);
-        let unused_rules = analysis.unused_rules();
-        let mut unused_diagnostics: Vec<_> = unused_rules
-            .filter(|(rule_name, _)| {
-                !config
-                    .always_used_rule_names
-                    .iter()
-                    .map(String::as_str)
-                    .chain(["WHITESPACE", "COMMENT"])
-                    .any(|name| name == *rule_name)
+    /// Returns the rules of every grammar in the workspace that match a query, best first.
+    #[allow(deprecated)]
+    pub fn symbol(&self, params: WorkspaceSymbolParams) -> Vec<SymbolInformation> {
+        let mut symbols: Vec<_> = self
+            .analyses
+            .iter()
+            .flat_map(|(uri, analysis)| analysis.rules.iter().map(move |rule| (uri, rule)))
+            .filter_map(|(uri, (name, ra))| {
+                Some((fuzzy_score(&params.query, name)?, uri, name, ra))
             })
-            .map(|(rule_name, range)| Diagnostic {
-                range,
-                severity: Some(DiagnosticSeverity::WARNING),
-                source: Some("Pest Language Server".to_owned()),
-                message: format!("Rule {} is unused", rule_name),
-                ..Default::default()
+            .collect();
+        symbols.sort_by(|(a, _, a_name, _), (b, _, b_name, _)| b.cmp(a).then(a_name.cmp(b_name)));
+
+        symbols
+            .into_iter()
+            .map(|(_, uri, name, ra)| SymbolInformation {
+                name: name.clone(),
+                kind: SymbolKind::FIELD,
+                tags: None,
+                deprecated: None,
+                location: Location::new(uri.clone(), ra.definition_location),
+                container_name: uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .map(str::to_owned),
             })
+            .collect()
+    }
+
+    pub fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Option<SemanticTokensResult> {
+        let uri = &params.text_document.uri;
+        let document = self.open_grammar(uri)?;
+        let pairs = parser::parse(Rule::grammar_rules, &document.text).ok()?;
+        let index = document.index(self.position_encoding);
+
+        Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: semantic_tokens(pairs, &index, None, &self.unit_analyses(uri)),
+        }))
+    }
+
+    pub fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Option<SemanticTokensRangeResult> {
+        let uri = &params.text_document.uri;
+        let document = self.open_grammar(uri)?;
+        let pairs = parser::parse(Rule::grammar_rules, &document.text).ok()?;
+        let index = document.index(self.position_encoding);
+        let unit = self.unit_analyses(uri);
+
+        Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: semantic_tokens(pairs, &index, Some(params.range), &unit),
+        }))
+    }
+
+    pub fn inlay_hint(&self, params: InlayHintParams) -> Option<Vec<InlayHint>> {
+        let uri = &params.text_document.uri;
+        let document = self.open_grammar(uri)?;
+        let pairs = parser::parse(Rule::grammar_rules, &document.text).ok()?;
+        let index = document.index(self.position_encoding);
+        Some(inlay_hints(
+            pairs,
+            &index,
+            params.range,
+            &self.unit_analyses(uri),
+        ))
+    }
+
+    pub fn execute_command(&self, params: ExecuteCommandParams) -> jsonrpc::Result<Option<Value>> {
+        let ExecuteCommandParams {
+            command, arguments, ..
+        } = params;
+
+        let Ok(command) = Command::from_str(&command) else {
+            return Err(jsonrpc::Error::invalid_params(format!(
+                "Unknown command {command}"
+            )));
+        };
+
+        match command {
+            Command::ParseInput => self.parse_input(arguments),
+            Command::GenerateDocs => self.generate_docs(arguments),
+        }
+    }
+
+    fn generate_docs(&self, mut arguments: Vec<Value>) -> jsonrpc::Result<Option<Value>> {
+        // The format is optional.
+        if arguments.len() == 1 {
+            arguments.push(Value::Null);
+        }
+
+        let (uri, format): (Url, Option<String>) = serde_json::from_value(Value::Array(arguments))
+            .map_err(|e| jsonrpc::Error::invalid_params(e.to_string()))?;
+
+        let format = match format {
+            Some(format) => DocFormat::from_str(&format).map_err(|_| {
+                jsonrpc::Error::invalid_params(format!("Unknown documentation format {format}"))
+            })?,
+            None => DocFormat::Markdown,
+        };
+
+        let documents: Vec<_> = self
+            .unit_of(&uri)
+            .iter()
+            .filter_map(|uri| Some((self.grammar_document(uri)?, self.analyses.get(uri)?)))
             .collect();
+        if documents.is_empty() {
+            return Err(jsonrpc::Error::invalid_params(format!(
+                "No grammar is tracked at {uri}"
+            )));
+        }
+
+        let title = Path::new(uri.path())
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        Ok(Some(Value::String(docs::generate(
+            &title, &documents, format,
+        ))))
+    }
+
+    fn parse_input(&self, arguments: Vec<Value>) -> jsonrpc::Result<Option<Value>> {
+        let (uri, rule, input): (Url, String, String) =
+            serde_json::from_value(Value::Array(arguments))
+                .map_err(|e| jsonrpc::Error::invalid_params(e.to_string()))?;
 
-        if config.always_used_rule_names.is_empty() && unused_diagnostics.len() == 1 {
-            unused_diagnostics.clear();
+        let ast = self
+            .analyses
+            .get(&uri)
+            .and_then(|analysis| analysis.ast.as_ref())
+            .ok_or_else(|| {
+                jsonrpc::Error::invalid_params(format!("No valid grammar is tracked at {uri}"))
+            })?;
+
+        if !ast.iter().any(|ast_rule| ast_rule.name == rule) {
+            return Err(jsonrpc::Error::invalid_params(format!(
+                "Rule {rule} is not defined in {uri}"
+            )));
         }
 
-        validate_pairs(pairs).map(|_| (analysis, unused_diagnostics))
+        let outcome = sample::parse_input(ast, &rule, &input, self.position_encoding);
+        serde_json::to_value(outcome)
+            .map(Some)
+            .map_err(|_| jsonrpc::Error::internal_error())
     }
 
+    /// Analyses every grammar unit, and every input file, again.
     async fn reload(&mut self) -> Diagnostics {
         self.client
             .log_message(MessageType::INFO, "Reloading all diagnostics".to_string())
             .await;
+
+        self.pending_changes.clear();
+        self.unit_rust_uris.clear();
+        self.units = self.grammar_units();
+        let units = self.units.clone();
+        let mut rust_uris = self.analyse_units(&units);
+        rust_uris.extend(
+            self.documents
+                .values()
+                .filter(|document| is_rust(document))
+                .map(|document| document.uri.clone()),
+        );
+
+        self.unit_diagnostics(&units, rust_uris)
+            .into_iter()
+            .chain(self.sample_diagnostics(None))
+            .collect()
+    }
+
+    /// Analyses the grammar units affected by changes to some documents again: those that
+    /// include a changed document, whose inline grammars or rule references are in a changed
+    /// Rust file, or whose members changed. Only the diagnostics of these units, of the changed
+    /// documents, and of the inputs associated with their grammars are returned.
+    async fn reload_changed(&mut self, changed: &[Url]) -> Diagnostics {
+        let units = self.grammar_units();
+        let previous_units = mem::replace(&mut self.units, units);
+        let units: Vec<_> = self
+            .units
+            .iter()
+            .filter(|unit| {
+                !previous_units.contains(unit)
+                    || unit.iter().any(|uri| {
+                        changed.contains(uri)
+                            || self
+                                .inline_grammar(uri)
+                                .is_some_and(|inline| changed.contains(&inline.source))
+                    })
+                    || self
+                        .unit_rust_sources(unit)
+                        .iter()
+                        .any(|(uri, _)| changed.contains(uri))
+                    || self
+                        .unit_rust_uris
+                        .get(*unit)
+                        .is_some_and(|uris| changed.iter().any(|uri| uris.contains(uri)))
+            })
+            .cloned()
+            .collect();
+        self.unit_rust_uris
+            .retain(|unit, _| self.units.contains(unit));
+
+        let mut rust_uris = self.analyse_units(&units);
+        rust_uris.extend(
+            changed
+                .iter()
+                .filter(|uri| {
+                    self.documents
+                        .get(uri)
+                        .is_some_and(|document| is_rust(document))
+                })
+                .cloned(),
+        );
+
+        let scope: Vec<_> = units.iter().flatten().chain(changed).cloned().collect();
+        self.unit_diagnostics(&units, rust_uris)
+            .into_iter()
+            .chain(self.sample_diagnostics(Some(&scope)))
+            .collect()
+    }
+
+    /// Records that a document changed, returning the revision of the change, to pass to
+    /// [Self::analyse_changes].
+    fn record_change(&mut self, uri: Url) -> u64 {
+        self.pending_changes.insert(uri);
+        self.revision += 1;
+        self.revision
+    }
+
+    /// Analyses the documents changed since they were last analysed, unless a change was made
+    /// after the one at `revision`, in which case it is left to that change, as the client is
+    /// still typing.
+    pub async fn analyse_changes(&mut self, revision: u64) {
+        if revision == self.revision {
+            self.analyse_pending().await;
+        }
+    }
+
+    /// Whether any document changed since it was last analysed.
+    pub fn has_pending_changes(&self) -> bool {
+        !self.pending_changes.is_empty()
+    }
+
+    /// Analyses the documents changed since they were last analysed.
+    pub async fn analyse_pending(&mut self) {
+        if self.pending_changes.is_empty() {
+            return;
+        }
+
+        let changed: Vec<_> = self.pending_changes.drain().collect();
+        for uri in &changed {
+            self.update_rust_source(uri);
+        }
+
+        let diagnostics = self.reload_changed(&changed).await;
+        self.send_diagnostics(diagnostics).await;
+    }
+
+    /// Analyses grammar units, storing the analyses and diagnostics of their documents. Returns
+    /// the Rust files the inline grammars among them are defined in.
+    fn analyse_units(&mut self, units: &[Vec<Url>]) -> HashSet<Url> {
+        let mut analyses = Vec::new();
+        let mut diagnostics = Vec::new();
+        let mut rust_uris = HashSet::new();
+        let mut unit_rust_uris = Vec::new();
+
+        for unit in units {
+            let documents: Vec<_> = unit
+                .iter()
+                .filter_map(|uri| self.grammar_document(uri))
+                .collect();
+            let rust_sources = self.unit_rust_sources(unit);
+            let results = analyse_unit(
+                &self.config,
+                &documents,
+                self.position_encoding,
+                |uri| {
+                    self.analyses
+                        .get(uri)
+                        .map(|analysis| analysis.rules.capacity())
+                },
+                |rule_name| {
+                    rust_sources
+                        .iter()
+                        .any(|(_, source)| source.rule_references.contains_key(rule_name))
+                },
+            );
+            unit_rust_uris.push((
+                unit.clone(),
+                rust_sources.iter().map(|(uri, _)| (*uri).clone()).collect(),
+            ));
+
+            for DocumentAnalysis {
+                uri,
+                analysis,
+                diagnostics: document_diagnostics,
+            } in results
+            {
+                let document_diagnostics = document_diagnostics
+                    .into_iter()
+                    .map(|diagnostic| self.map_related_information(diagnostic));
+
+                // Inline grammars are reported in their Rust files, and are not analysed
+                // further.
+                if let Some(inline) = self.inline_grammar(&uri) {
+                    rust_uris.insert(inline.source.clone());
+                    let document_diagnostics = document_diagnostics
+                        .map(|diagnostic| Diagnostic {
+                            range: inline.map_range(diagnostic.range, self.position_encoding),
+                            ..diagnostic
+                        })
+                        .collect();
+                    diagnostics.push((uri, document_diagnostics));
+                    continue;
+                }
+
+                diagnostics.push((uri.clone(), document_diagnostics.collect()));
+                analyses.extend(analysis.map(|analysis| (uri, analysis)));
+            }
+        }
+
+        self.diagnostics.extend(diagnostics);
+        self.analyses.extend(analyses);
+        self.unit_rust_uris.extend(unit_rust_uris);
+        rust_uris
+    }
+
+    /// Returns the stored diagnostics of the documents of grammar units, and of Rust files,
+    /// which hold the diagnostics of their inline grammars.
+    fn unit_diagnostics(&self, units: &[Vec<Url>], rust_uris: HashSet<Url>) -> Diagnostics {
+        let grammar_diagnostics = units
+            .iter()
+            .flatten()
+            .filter_map(|uri| self.documents.get(uri))
+            .map(|document| {
+                PublishDiagnosticsParams::new(
+                    document.uri.clone(),
+                    self.diagnostics
+                        .get(&document.uri)
+                        .cloned()
+                        .unwrap_or_default(),
+                    Some(document.version),
+                )
+            });
+
+        let inline_diagnostics = rust_uris.into_iter().map(|uri| {
+            let diagnostics = self
+                .rust_sources
+                .get(&uri)
+                .into_iter()
+                .flat_map(|source| &source.parsers)
+                .flat_map(|parser| &parser.grammars)
+                .filter_map(|grammar| self.diagnostics.get(grammar.uri()))
+                .flatten()
+                .cloned()
+                .collect();
+            let version = self.documents.get(&uri).map(|document| document.version);
+            PublishDiagnosticsParams::new(uri, diagnostics, version)
+        });
+
+        grammar_diagnostics.chain(inline_diagnostics).collect()
+    }
+
+    /// Groups the tracked grammars into units, from the configuration and the parsers derived
+    /// in Rust sources. Units only include their tracked files and inline grammars, and any
+    /// grammar not in a unit forms a unit of its own.
+    fn grammar_units(&self) -> Vec<Vec<Url>> {
+        let mut units: Vec<Vec<Url>> = Vec::new();
+        let configured = self.workspace_roots.iter().flat_map(|root| {
+            self.config
+                .grammar_units
+                .iter()
+                .map(|files| resolve_unit(files, root))
+        });
+
+        let derived = self.parsers().map(|parser| {
+            parser
+                .grammars
+                .iter()
+                .map(GrammarSource::uri)
+                .cloned()
+                .collect()
+        });
+
+        for unit in configured.chain(derived) {
+            let unit: Vec<_> = unit
+                .into_iter()
+                .filter(|uri| self.grammar_document(uri).is_some())
+                .filter(|uri| !units.iter().flatten().any(|member| member == uri))
+                .collect();
+
+            if !unit.is_empty() {
+                units.push(unit);
+            }
+        }
+
+        let grammars = self
+            .documents
+            .values()
+            .map(Deref::deref)
+            .chain(self.workspace_grammars.values());
+        for uri in grammars.filter(|d| is_grammar(d)).map(|d| &d.uri) {
+            if !units.iter().flatten().any(|member| member == uri) {
+                units.push(vec![uri.clone()]);
+            }
+        }
+
+        units
+    }
+
+    /// Returns the documents in the same grammar unit as `uri`, including itself.
+    fn unit_of<'a>(&'a self, uri: &'a Url) -> &'a [Url] {
+        self.units
+            .iter()
+            .find(|unit| unit.contains(uri))
+            .map_or(std::slice::from_ref(uri), Vec::as_slice)
+    }
+
+    /// Returns the analyses of the documents in the same grammar unit as `uri`, including its
+    /// own.
+    fn unit_analyses(&self, uri: &Url) -> Vec<&Analysis> {
+        self.unit_of(uri)
+            .iter()
+            .filter_map(|uri| self.analyses.get(uri))
+            .collect()
+    }
+
+    /// Returns a tracked document, a grammar in the workspace, or a grammar inline in a Rust
+    /// source file.
+    fn grammar_document(&self, uri: &Url) -> Option<&TextDocumentItem> {
         self.documents
+            .get(uri)
+            .map(Deref::deref)
+            .or_else(|| self.workspace_grammars.get(uri))
+            .or_else(|| Some(&self.inline_grammar(uri)?.document))
+    }
+
+    fn parsers(&self) -> impl Iterator<Item = &DerivedParser> {
+        self.rust_sources
+            .values()
+            .flat_map(|source| &source.parsers)
+    }
+
+    fn inline_grammar(&self, uri: &Url) -> Option<&InlineGrammar> {
+        self.parsers()
+            .flat_map(|parser| &parser.grammars)
+            .find_map(|grammar| match grammar {
+                GrammarSource::Inline(inline) if inline.document.uri == *uri => Some(inline),
+                _ => None,
+            })
+    }
+
+    /// Maps related information in inline grammars to their Rust source files.
+    fn map_related_information(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        for related in diagnostic.related_information.iter_mut().flatten() {
+            if let Some(inline) = self.inline_grammar(&related.location.uri) {
+                related.location = inline.map_location(&related.location, self.position_encoding);
+            }
+        }
+
+        diagnostic
+    }
+
+    /// Returns the Rust sources that can reference the rules of a grammar unit: those in the
+    /// crates that contain or derive a parser from one of its grammars, or the whole workspace
+    /// if a grammar is in neither.
+    fn unit_rust_sources(&self, unit: &[Url]) -> Vec<(&Url, &RustSource)> {
+        let mut crate_roots: Vec<PathBuf> = Vec::new();
+        let mut whole_workspace = false;
+        for uri in unit {
+            let deriving = self
+                .rust_sources
+                .values()
+                .filter(|source| {
+                    source
+                        .parsers
+                        .iter()
+                        .flat_map(|parser| &parser.grammars)
+                        .any(|grammar| grammar.uri() == uri)
+                })
+                .filter_map(|source| source.crate_root.clone());
+            let containing = uri
+                .to_file_path()
+                .ok()
+                .and_then(|path| crate_root(&path).map(Path::to_owned));
+
+            let roots: Vec<_> = deriving.chain(containing).collect();
+            whole_workspace |= roots.is_empty();
+            crate_roots.extend(roots);
+        }
+
+        self.rust_sources
             .iter()
-            .map(|(url, document)| {
-                let capacity = self
-                    .analyses
-                    .get(url)
-                    .map(|analysis| analysis.rules.capacity());
-                let diagnostics = match Self::analyse_document(&self.config, document, capacity) {
-                    Ok((analysis, diagnostics)) => {
-                        self.analyses.insert(url.clone(), analysis);
-                        diagnostics
-                    }
-                    Err(errors) => errors.into_diagnostics(),
-                };
+            .filter(|(_, source)| {
+                whole_workspace
+                    || source
+                        .crate_root
+                        .as_ref()
+                        .is_some_and(|root| crate_roots.contains(root))
+            })
+            .collect()
+    }
 
-                PublishDiagnosticsParams::new(url.clone(), diagnostics, Some(document.version))
+    /// Returns the `Rule::name` references to a rule of the grammar unit of `uri` in Rust code.
+    fn rust_references(&self, uri: &Url, rule_name: &str) -> Vec<Location> {
+        self.unit_rust_sources(self.unit_of(uri))
+            .into_iter()
+            .flat_map(|(uri, source)| {
+                source
+                    .rule_references
+                    .get(rule_name)
+                    .into_iter()
+                    .flatten()
+                    .map(|range| Location::new(uri.clone(), *range))
             })
             .collect()
     }
 
+    /// Reads a grammar in the workspace roots from disk again, after it is created, changed,
+    /// closed or moved, and forgets it if it no longer exists.
+    fn index_grammar(&mut self, uri: &Url) {
+        let Some(path) = uri.to_file_path().ok().filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "pest")
+                && self
+                    .workspace_roots
+                    .iter()
+                    .any(|root| path.starts_with(root))
+        }) else {
+            return;
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let document = TextDocumentItem::new(uri.clone(), "pest".to_owned(), 0, text);
+                self.workspace_grammars.insert(uri.clone(), document);
+            }
+            Err(_) => _ = self.workspace_grammars.remove(uri),
+        }
+    }
+
+    /// Scans a Rust file again, after it is opened, changed, closed or moved. Files that are
+    /// not tracked are read from disk, and forgotten if they no longer exist.
+    fn update_rust_source(&mut self, uri: &Url) {
+        let source = match self.documents.get(uri) {
+            Some(document) if is_rust(document) => {
+                RustSource::new(uri, &document.text, self.position_encoding)
+            }
+            Some(_) => return,
+            None if uri.path().ends_with(".rs") => {
+                let text = uri
+                    .to_file_path()
+                    .ok()
+                    .and_then(|path| fs::read_to_string(path).ok());
+                match text
+                    .map(|text| RustSource::new(uri, &text, self.position_encoding))
+                    .filter(|source| !source.is_empty())
+                {
+                    Some(source) => source,
+                    None => {
+                        self.rust_sources.remove(uri);
+                        return;
+                    }
+                }
+            }
+            None => return,
+        };
+
+        self.rust_sources.insert(uri.clone(), source);
+    }
+
+    /// Drops what was derived from a document that is no longer tracked, or no longer exists
+    /// under `uri`, and clears its diagnostics in the client. Rust files and grammars in the
+    /// workspace are read from disk again, as they still count if they exist.
+    async fn forget_document(&mut self, uri: &Url) {
+        self.analyses.remove(uri);
+        self.diagnostics.remove(uri);
+        self.pending_changes.remove(uri);
+        self.index_grammar(uri);
+        self.update_rust_source(uri);
+        self.client
+            .publish_diagnostics(uri.clone(), Vec::new(), None)
+            .await;
+    }
+
+    /// Parses every input file associated with a grammar, either open in the editor or matched
+    /// by the `sampleInputs` configuration. If a `scope` is given, only the inputs in it, or
+    /// associated with a grammar in it, are parsed.
+    fn sample_diagnostics(&mut self, scope: Option<&[Url]>) -> Diagnostics {
+        let in_scope = |uri: &Url, grammar: &Url| {
+            scope.is_none_or(|scope| scope.contains(uri) || scope.contains(grammar))
+        };
+        let previous_inputs = mem::take(&mut self.sample_inputs);
+        let mut inputs = HashMap::new();
+        let mut parsers = SampleParsers::default();
+        let mut diagnostics = Vec::new();
+
+        for document in self.documents.values().filter(|d| !is_grammar(d)) {
+            let Some(association) = SampleAssociation::from_header(&document.uri, &document.text)
+                .or_else(|| self.configured_association(&document.uri))
+                .filter(|association| in_scope(&document.uri, &association.grammar))
+            else {
+                continue;
+            };
+
+            let analysis = self.analyses.get(&association.grammar);
+            diagnostics.push(PublishDiagnosticsParams::new(
+                document.uri.clone(),
+                association.diagnostics(
+                    analysis,
+                    &mut parsers,
+                    &document.text,
+                    self.position_encoding,
+                ),
+                Some(document.version),
+            ));
+            inputs.insert(document.uri.clone(), association.grammar);
+        }
+
+        for root in &self.workspace_roots {
+            for sample_input in &self.config.sample_inputs {
+                let Some(association) = sample_input.association(root) else {
+                    continue;
+                };
+
+                // Globbing is slow in large workspaces, so it is skipped unless an input of the
+                // entry, or the grammar of one, changed.
+                let matches = |uri: &Url| {
+                    uri.to_file_path()
+                        .is_ok_and(|path| sample_input.matches(root, &path))
+                };
+                let affected = scope.is_none_or(|scope| {
+                    scope.contains(&association.grammar)
+                        || scope.iter().any(matches)
+                        || previous_inputs
+                            .iter()
+                            .any(|(uri, grammar)| scope.contains(grammar) && matches(uri))
+                });
+                if !affected {
+                    continue;
+                }
+
+                for path in sample_input.files(root) {
+                    let Ok(uri) = Url::from_file_path(&path) else {
+                        continue;
+                    };
+
+                    // A header in the input can associate it with another grammar, which it
+                    // was last parsed with.
+                    let out_of_scope = !in_scope(&uri, &association.grammar)
+                        && !previous_inputs
+                            .get(&uri)
+                            .is_some_and(|grammar| in_scope(&uri, grammar));
+                    if self.documents.contains_key(&uri) || out_of_scope {
+                        continue;
+                    }
+
+                    let Ok(text) = fs::read_to_string(&path) else {
+                        continue;
+                    };
+
+                    let association =
+                        SampleAssociation::from_header(&uri, &text).unwrap_or(association.clone());
+                    if !in_scope(&uri, &association.grammar) {
+                        continue;
+                    }
+
+                    let analysis = self.analyses.get(&association.grammar);
+                    diagnostics.push(PublishDiagnosticsParams::new(
+                        uri.clone(),
+                        association.diagnostics(
+                            analysis,
+                            &mut parsers,
+                            &text,
+                            self.position_encoding,
+                        ),
+                        None,
+                    ));
+                    inputs.insert(uri, association.grammar);
+                }
+            }
+        }
+
+        // Inputs out of scope were not parsed again, and keep their diagnostics. Those in
+        // scope that were not parsed no longer match the configuration, and lose them.
+        for (uri, grammar) in previous_inputs {
+            if inputs.contains_key(&uri) {
+                continue;
+            }
+
+            if in_scope(&uri, &grammar) {
+                diagnostics.push(PublishDiagnosticsParams::new(uri, Vec::new(), None));
+            } else {
+                inputs.insert(uri, grammar);
+            }
+        }
+
+        self.sample_inputs = inputs;
+        diagnostics
+    }
+
+    fn configured_association(&self, uri: &Url) -> Option<SampleAssociation> {
+        let path = uri.to_file_path().ok()?;
+        self.workspace_roots.iter().find_map(|root| {
+            self.config
+                .sample_inputs
+                .iter()
+                .find(|sample_input| sample_input.matches(root, &path))?
+                .association(root)
+        })
+    }
+
     async fn send_diagnostics(&self, diagnostics: Diagnostics) {
         for PublishDiagnosticsParams {
             uri,
@@ -637,9 +2065,92 @@ impl PestLanguageServerImpl {
         }
     }
 
+    /// Returns the identifier at a position in a document, and its range, which are empty if
+    /// there is none.
+    /// Returns an open document if it is a grammar, rather than a sample input.
+    fn open_grammar(&self, uri: &Url) -> Option<&Document> {
+        self.documents
+            .get(uri)
+            .filter(|document| is_grammar(document))
+    }
+
+    fn identifier_at<'a>(&self, document: &'a Document, position: Position) -> (&'a str, Range) {
+        let index = document.index(self.position_encoding);
+        let range = identifier_at(&document.text, index.offset(position));
+        (&document.text[range.clone()], index.range(range))
+    }
+
     fn rule_analysis(&self, uri: &Url, rule_name: &str) -> Option<&RuleAnalysis> {
         self.analyses
             .get(uri)
             .and_then(|analysis| analysis.rules.get(rule_name))
     }
+
+    /// Finds the definition of a rule anywhere in the grammar unit of `uri`, returning the
+    /// document it is defined in.
+    fn rule_definition<'a>(
+        &'a self,
+        uri: &'a Url,
+        rule_name: &str,
+    ) -> Option<(&'a Url, &'a RuleAnalysis)> {
+        self.unit_of(uri)
+            .iter()
+            .find_map(|uri| Some((uri, self.rule_analysis(uri, rule_name)?)))
+    }
+
+    /// Returns every occurrence of a rule in the grammar unit of `uri`, including its
+    /// definition, or nothing if the rule is not defined in the unit.
+    fn rule_locations(&self, uri: &Url, rule_name: &str) -> Vec<Location> {
+        let Some((definition_uri, ra)) = self.rule_definition(uri, rule_name) else {
+            return Vec::new();
+        };
+
+        let references = self.unit_of(uri).iter().flat_map(|uri| {
+            self.analyses
+                .get(uri)
+                .and_then(|analysis| analysis.identifiers.get(rule_name))
+                .into_iter()
+                .flatten()
+                .map(|range| Location::new(uri.clone(), *range))
+        });
+
+        references
+            .chain(iter::once(Location::new(
+                definition_uri.clone(),
+                ra.identifier_location,
+            )))
+            .collect()
+    }
+}
+
+fn grammar_doc_hover(doc: String, range: Range) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: doc,
+        }),
+        range: Some(range),
+    }
+}
+
+/// Returns an LSP `RequestFailed` error, for requests that are valid but cannot be carried out.
+fn request_failed(message: String) -> jsonrpc::Error {
+    jsonrpc::Error {
+        code: jsonrpc::ErrorCode::ServerError(-32803),
+        message: message.into(),
+        data: None,
+    }
+}
+
+fn call_hierarchy_item(uri: Url, name: &str, ra: &RuleAnalysis) -> CallHierarchyItem {
+    CallHierarchyItem {
+        name: name.to_owned(),
+        kind: SymbolKind::FUNCTION,
+        tags: None,
+        detail: None,
+        uri,
+        range: ra.definition_location,
+        selection_range: ra.identifier_location,
+        data: None,
+    }
 }
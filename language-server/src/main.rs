@@ -1,19 +1,34 @@
-use std::io::{stdin, stdout};
+use std::{
+    io::{stdin, stdout},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
 
-use capabilities::capabilities;
 use clap::command;
 use lsp::PestLanguageServerImpl;
-use smol::{Unblock, lock::RwLock};
+use serde_json::Value;
+use smol::{
+    Timer, Unblock,
+    lock::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 use tower_lsp::{
     Client, LanguageServer, LspService, Server,
     jsonrpc::Result,
     lsp_types::{
+        CallHierarchyIncomingCall, CallHierarchyIncomingCallsParams, CallHierarchyItem,
+        CallHierarchyOutgoingCall, CallHierarchyOutgoingCallsParams, CallHierarchyPrepareParams,
         CodeActionParams, CodeActionResponse, CompletionParams, CompletionResponse,
-        DeleteFilesParams, DidChangeConfigurationParams, DidChangeTextDocumentParams,
-        DidOpenTextDocumentParams, DocumentFormattingParams, DocumentSymbolParams,
-        DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverParams,
-        InitializeParams, InitializeResult, InitializedParams, Location, ReferenceParams,
-        RenameParams, TextEdit, WorkspaceEdit,
+        CreateFilesParams, DeleteFilesParams, DidChangeConfigurationParams,
+        DidChangeTextDocumentParams, DidChangeWatchedFilesParams, DidCloseTextDocumentParams,
+        DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentFormattingParams,
+        DocumentSymbolParams, DocumentSymbolResponse, ExecuteCommandParams, GotoDefinitionParams,
+        GotoDefinitionResponse, Hover, HoverParams, InitializeParams, InitializeResult,
+        InitializedParams, InlayHint, InlayHintParams, Location, PrepareRenameResponse,
+        ReferenceParams, RenameFilesParams, RenameParams, SemanticTokensParams,
+        SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult,
+        SymbolInformation, TextDocumentPositionParams, TextEdit, WorkspaceEdit,
+        WorkspaceSymbolParams,
         request::{GotoDeclarationParams, GotoDeclarationResponse},
     },
 };
@@ -21,27 +36,69 @@ use tower_lsp::{
 mod analysis;
 mod builtins;
 mod capabilities;
+mod check;
+mod commands;
+mod completion;
+mod cycles;
+mod docs;
 mod helpers;
+mod inlay_hints;
+mod line_index;
 mod lsp;
+mod rust;
+mod sample;
+mod semantic_tokens;
+mod symbols;
+mod units;
+
+/// How long to wait for further changes to a document before analysing it.
+const ANALYSIS_DELAY: Duration = Duration::from_millis(200);
 
 #[derive(Debug)]
 /// The async-ready language server. You probably want [PestLanguageServerImpl] instead.
-pub struct PestLanguageServer(RwLock<PestLanguageServerImpl>);
+pub struct PestLanguageServer(Arc<RwLock<PestLanguageServerImpl>>);
 
 impl PestLanguageServer {
     pub fn new(client: Client) -> Self {
-        Self(RwLock::new(PestLanguageServerImpl::new(client)))
+        Self(Arc::new(RwLock::new(PestLanguageServerImpl::new(client))))
+    }
+
+    /// Returns the server once the documents changed since they were last analysed are
+    /// analysed, so that requests are not served positions from stale analyses.
+    async fn analysed(&self) -> RwLockReadGuard<'_, PestLanguageServerImpl> {
+        let server = self.0.read().await;
+        if !server.has_pending_changes() {
+            return server;
+        }
+
+        drop(server);
+        let mut server = self.0.write().await;
+        server.analyse_pending().await;
+        RwLockWriteGuard::downgrade(server)
     }
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for PestLanguageServer {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
-        Ok(capabilities())
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        Ok(self.0.write().await.initialize(params))
     }
 
     async fn initialized(&self, params: InitializedParams) {
-        self.0.write().await.initialized(params).await;
+        let read_workspace = {
+            let mut server = self.0.write().await;
+            server.initialized(params).await;
+            server.workspace_reader()
+        };
+
+        // The workspace is read in a task of its own, so that the server is not held up while
+        // reading it, and analysed once read.
+        let server = self.0.clone();
+        smol::spawn(async move {
+            let index = smol::unblock(read_workspace).await;
+            server.write().await.index_workspace(index).await;
+        })
+        .detach();
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -57,27 +114,65 @@ impl LanguageServer for PestLanguageServer {
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        self.0.write().await.did_change(params).await;
+        let Some(revision) = self.0.write().await.did_change(params).await else {
+            return;
+        };
+
+        // Changes are analysed once the client stops typing, rather than on every keystroke.
+        // The wait happens in a task of its own, so that requests are not held up behind it.
+        let server = self.0.clone();
+        smol::spawn(async move {
+            Timer::after(ANALYSIS_DELAY).await;
+            server.write().await.analyse_changes(revision).await;
+        })
+        .detach();
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        self.0.write().await.did_save(params).await;
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.0.write().await.did_close(params).await;
+    }
+
+    async fn did_create_files(&self, params: CreateFilesParams) {
+        self.0.write().await.did_create_files(params).await;
+    }
+
+    async fn did_rename_files(&self, params: RenameFilesParams) {
+        self.0.write().await.did_rename_files(params).await;
     }
 
     async fn did_delete_files(&self, params: DeleteFilesParams) {
         self.0.write().await.did_delete_files(params).await;
     }
 
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        self.0.write().await.did_change_watched_files(params).await;
+    }
+
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
-        Ok(Some(self.0.read().await.code_action(params).await))
+        Ok(Some(self.analysed().await.code_action(params).await))
     }
 
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        Ok(self.0.read().await.completion(params))
+        Ok(self.analysed().await.completion(params))
     }
 
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
-        Ok(self.0.read().await.hover(params))
+        Ok(self.analysed().await.hover(params))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        self.analysed().await.prepare_rename(params)
     }
 
     async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
-        Ok(Some(self.0.read().await.rename(params)))
+        self.analysed().await.rename(params)
     }
 
     async fn goto_declaration(
@@ -85,8 +180,7 @@ impl LanguageServer for PestLanguageServer {
         params: GotoDeclarationParams,
     ) -> Result<Option<GotoDeclarationResponse>> {
         let declaration = self
-            .0
-            .read()
+            .analysed()
             .await
             .goto_definition(params.text_document_position_params)
             .map(GotoDeclarationResponse::Scalar);
@@ -98,8 +192,7 @@ impl LanguageServer for PestLanguageServer {
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
         let definition = self
-            .0
-            .read()
+            .analysed()
             .await
             .goto_definition(params.text_document_position_params)
             .map(GotoDefinitionResponse::Scalar);
@@ -107,7 +200,7 @@ impl LanguageServer for PestLanguageServer {
     }
 
     async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-        Ok(self.0.read().await.references(params))
+        Ok(self.analysed().await.references(params))
     }
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
@@ -118,16 +211,75 @@ impl LanguageServer for PestLanguageServer {
         &self,
         params: DocumentSymbolParams,
     ) -> Result<Option<DocumentSymbolResponse>> {
-        Ok(self.0.read().await.document_symbol(params))
+        Ok(self.analysed().await.document_symbol(params))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        Ok(Some(self.analysed().await.symbol(params)))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<CallHierarchyItem>>> {
+        Ok(self.analysed().await.prepare_call_hierarchy(params))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        Ok(self.analysed().await.incoming_calls(params))
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        Ok(self.analysed().await.outgoing_calls(params))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        Ok(self.analysed().await.semantic_tokens_full(params))
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        Ok(self.analysed().await.semantic_tokens_range(params))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        Ok(self.analysed().await.inlay_hint(params))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        self.analysed().await.execute_command(params)
     }
 }
 
-fn main() {
-    let _args = command!().get_matches();
+fn main() -> ExitCode {
+    let matches = command!()
+        .subcommand(check::subcommand())
+        .subcommand(docs::subcommand())
+        .get_matches();
+    match matches.subcommand() {
+        Some(("check", matches)) => return check::run(matches),
+        Some(("doc", matches)) => return docs::run(matches),
+        _ => {}
+    }
 
     let stdin = Unblock::new(stdin());
     let stdout = Unblock::new(stdout());
 
     let (service, socket) = LspService::new(PestLanguageServer::new);
     smol::block_on(Server::new(stdin, stdout, socket).serve(service));
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    iter,
+    path::{Component, Path, PathBuf},
+};
+
+use proc_macro2::{Spacing, TokenStream, TokenTree};
+use syn::{
+    Attribute, Expr, ExprLit, Ident, ItemEnum, ItemStruct, Lit, LitStr, Meta, MetaNameValue, Token,
+    punctuated::Punctuated,
+    visit::{self, Visit},
+};
+use tower_lsp::lsp_types::{Location, Position, Range, TextDocumentItem, Url};
+
+use crate::{
+    helpers::find_files,
+    line_index::{LineIndex, PositionEncoding},
+};
+
+#[derive(Debug, Default)]
+/// The parts of a Rust source file that relate to pest grammars.
+pub struct RustSource {
+    /// The directory of the crate the file belongs to, if any.
+    pub crate_root: Option<PathBuf>,
+    pub parsers: Vec<DerivedParser>,
+    /// The occurrences of `Rule::name` paths, by rule name.
+    pub rule_references: HashMap<String, Vec<Range>>,
+}
+
+impl RustSource {
+    /// Scans a Rust source file, with positions in `encoding`.
+    pub fn new(uri: &Url, text: &str, encoding: PositionEncoding) -> Self {
+        let path = uri.to_file_path().ok();
+        let index = LineIndex::new(text, encoding);
+        let mut rule_references = HashMap::new();
+        if let Ok(tokens) = text.parse() {
+            find_rule_references(tokens, &index, &mut rule_references);
+        }
+
+        let source = Self {
+            crate_root: path.as_deref().and_then(crate_root).map(Path::to_owned),
+            parsers: find_parsers(uri, text, &index),
+            rule_references,
+        };
+
+        // Every span is resolved by now, so free the source text proc_macro2 keeps for them.
+        proc_macro2::extra::invalidate_current_thread_spans();
+        source
+    }
+
+    /// Returns whether the file is relevant to the server at all.
+    pub fn is_empty(&self) -> bool {
+        self.parsers.is_empty() && self.rule_references.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A parser derived with `#[derive(Parser)]` in a Rust source file.
+pub struct DerivedParser {
+    /// The grammars of the parser, in the order pest_derive concatenates them.
+    pub grammars: Vec<GrammarSource>,
+}
+
+#[derive(Debug, Clone)]
+pub enum GrammarSource {
+    /// A `#[grammar = "path"]` attribute, resolved as pest_derive does.
+    File(Url),
+    /// A `#[grammar_inline = "..."]` attribute.
+    Inline(InlineGrammar),
+}
+
+#[derive(Debug, Clone)]
+/// A grammar embedded in a Rust string literal.
+pub struct InlineGrammar {
+    /// The grammar, as a document with a URI of the Rust file and a fragment identifying the
+    /// attribute. It is never sent to the editor.
+    pub document: TextDocumentItem,
+    /// The Rust file the grammar is defined in.
+    pub source: Url,
+    /// The literal as written in the Rust file, including quotes and escapes.
+    literal: String,
+    /// The position of the start of the literal in the Rust file.
+    start: Position,
+    /// The byte offset in `literal` of each byte of the grammar, and of its end. [None] if the
+    /// literal could not be mapped, in which case positions map to the start of the literal.
+    offsets: Option<Vec<usize>>,
+}
+
+impl GrammarSource {
+    /// Returns the URI the grammar is tracked under.
+    pub fn uri(&self) -> &Url {
+        match self {
+            GrammarSource::File(uri) => uri,
+            GrammarSource::Inline(inline) => &inline.document.uri,
+        }
+    }
+}
+
+impl InlineGrammar {
+    fn new(source: &Url, id: &str, literal: &LitStr, index: &LineIndex) -> Self {
+        let mut uri = source.clone();
+        uri.set_fragment(Some(id));
+
+        let text = literal.value();
+        let repr = literal.token().to_string();
+        let offsets = literal_offsets(&repr).filter(|(decoded, _)| *decoded == text);
+
+        Self {
+            document: TextDocumentItem::new(uri, "pest".to_owned(), 0, text),
+            source: source.clone(),
+            literal: repr,
+            start: index.position(literal.span().byte_range().start),
+            offsets: offsets.map(|(_, offsets)| offsets),
+        }
+    }
+
+    /// Maps a range in the grammar to the corresponding range in the Rust file, both in the
+    /// `encoding` the file was scanned with.
+    pub fn map_range(&self, range: Range, encoding: PositionEncoding) -> Range {
+        Range::new(
+            self.map_position(range.start, encoding),
+            self.map_position(range.end, encoding),
+        )
+    }
+
+    /// Maps a location in the grammar to the Rust file.
+    pub fn map_location(&self, location: &Location, encoding: PositionEncoding) -> Location {
+        Location::new(
+            self.source.clone(),
+            self.map_range(location.range, encoding),
+        )
+    }
+
+    fn map_position(&self, position: Position, encoding: PositionEncoding) -> Position {
+        let Some(offsets) = &self.offsets else {
+            return self.start;
+        };
+
+        let offset = LineIndex::new(&self.document.text, encoding)
+            .offset(position)
+            .min(offsets.len() - 1);
+        let before = &self.literal[..offsets[offset]];
+        match before.rsplit_once('\n') {
+            Some((lines, column)) => Position::new(
+                self.start.line + lines.matches('\n').count() as u32 + 1,
+                encoding.columns(column),
+            ),
+            None => Position::new(
+                self.start.line,
+                self.start.character + encoding.columns(before),
+            ),
+        }
+    }
+}
+
+/// Finds the parsers derived in a Rust source file.
+fn find_parsers(uri: &Url, text: &str, index: &LineIndex) -> Vec<DerivedParser> {
+    let (Ok(file), Ok(path)) = (syn::parse_file(text), uri.to_file_path()) else {
+        return Vec::new();
+    };
+
+    let mut visitor = ParserVisitor {
+        uri,
+        path: &path,
+        index,
+        parsers: Vec::new(),
+    };
+    visitor.visit_file(&file);
+    visitor.parsers
+}
+
+/// Finds the Rust source files under `root`, skipping build output and hidden directories.
+pub fn rust_files(root: &Path) -> Vec<PathBuf> {
+    find_files(root, "rs")
+}
+
+/// Returns whether a document is a Rust source file.
+pub fn is_rust(document: &TextDocumentItem) -> bool {
+    document.language_id == "rust" || document.uri.path().ends_with(".rs")
+}
+
+struct ParserVisitor<'a> {
+    uri: &'a Url,
+    path: &'a Path,
+    index: &'a LineIndex<'a>,
+    parsers: Vec<DerivedParser>,
+}
+
+impl ParserVisitor<'_> {
+    fn visit_derive(&mut self, attrs: &[Attribute], ident: &Ident) {
+        if !derives_parser(attrs) {
+            return;
+        }
+
+        let mut grammars = Vec::new();
+        for attr in attrs {
+            let Meta::NameValue(MetaNameValue {
+                path,
+                value:
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(literal),
+                        ..
+                    }),
+                ..
+            }) = &attr.meta
+            else {
+                continue;
+            };
+
+            if path.is_ident("grammar") {
+                if let Some(grammar) = resolve_grammar(self.path, &literal.value()) {
+                    grammars.push(GrammarSource::File(grammar));
+                }
+            } else if path.is_ident("grammar_inline") {
+                let id = format!("{ident}-{}", grammars.len());
+                let grammar = InlineGrammar::new(self.uri, &id, literal, self.index);
+                grammars.push(GrammarSource::Inline(grammar));
+            }
+        }
+
+        // Other crates, such as clap, also name their derive macros `Parser`.
+        if !grammars.is_empty() {
+            self.parsers.push(DerivedParser { grammars });
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for ParserVisitor<'_> {
+    fn visit_item_struct(&mut self, item: &'ast ItemStruct) {
+        self.visit_derive(&item.attrs, &item.ident);
+        visit::visit_item_struct(self, item);
+    }
+
+    fn visit_item_enum(&mut self, item: &'ast ItemEnum) {
+        self.visit_derive(&item.attrs, &item.ident);
+        visit::visit_item_enum(self, item);
+    }
+}
+
+/// Finds `Rule::name` paths, including in macro invocations, which syn does not parse.
+fn find_rule_references(
+    tokens: TokenStream,
+    line_index: &LineIndex,
+    references: &mut HashMap<String, Vec<Range>>,
+) {
+    let tokens: Vec<_> = tokens.into_iter().collect();
+    for (index, token) in tokens.iter().enumerate() {
+        match (token, tokens.get(index + 1..index + 4)) {
+            (TokenTree::Group(group), _) => {
+                find_rule_references(group.stream(), line_index, references)
+            }
+            (
+                TokenTree::Ident(rule),
+                Some(
+                    [
+                        TokenTree::Punct(first),
+                        TokenTree::Punct(second),
+                        TokenTree::Ident(name),
+                    ],
+                ),
+            ) if rule == "Rule"
+                && first.as_char() == ':'
+                && first.spacing() == Spacing::Joint
+                && second.as_char() == ':' =>
+            {
+                references
+                    .entry(name.to_string())
+                    .or_default()
+                    .push(line_index.range(name.span().byte_range()));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn derives_parser(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("derive"))
+        .filter_map(|attr| {
+            attr.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated)
+                .ok()
+        })
+        .flatten()
+        .any(|path| path.segments.last().is_some_and(|s| s.ident == "Parser"))
+}
+
+/// Returns the directory of the crate a source file belongs to, i.e. the nearest directory
+/// with a `Cargo.toml`.
+pub fn crate_root(source: &Path) -> Option<&Path> {
+    source
+        .ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+}
+
+/// Resolves the path of a `#[grammar]` attribute as pest_derive does: relative to the crate
+/// root, or to its `src` directory if the file does not exist there.
+fn resolve_grammar(source: &Path, grammar: &str) -> Option<Url> {
+    let root = crate_root(source)?;
+    let path = match root.join(grammar) {
+        path if path.exists() => path,
+        _ => root.join("src").join(grammar),
+    };
+
+    Url::from_file_path(normalize(&path)).ok()
+}
+
+/// Removes the `.` and `..` components of a path without resolving symbolic links, so that it
+/// matches the paths of documents the editor opens through the same links.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Decodes a string literal as written in Rust source, returning its value and the byte offset
+/// in the literal of each byte of the value, followed by the offset of its end.
+fn literal_offsets(literal: &str) -> Option<(String, Vec<usize>)> {
+    let mut value = String::new();
+    let mut offsets = Vec::new();
+
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let start = 1 + hashes + 1;
+        let end = literal.len().checked_sub(1 + hashes)?;
+        value.push_str(literal.get(start..end)?);
+        offsets.extend(start..=end);
+        return Some((value, offsets));
+    }
+
+    let body = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut chars = body.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let offset = index + 1;
+        let decoded = if c != '\\' {
+            c
+        } else {
+            match chars.next()?.1 {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                escaped @ ('\\' | '\'' | '"') => escaped,
+                'x' => {
+                    let hex: String = (0..2)
+                        .filter_map(|_| chars.next())
+                        .map(|(_, c)| c)
+                        .collect();
+                    char::from(u8::from_str_radix(&hex, 16).ok()?)
+                }
+                'u' => {
+                    let hex: String = chars
+                        .by_ref()
+                        .map(|(_, c)| c)
+                        .skip_while(|c| *c == '{')
+                        .take_while(|c| *c != '}')
+                        .collect();
+                    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                }
+                '\n' | '\r' => {
+                    // A line continuation skips the newline and any leading whitespace.
+                    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                    continue;
+                }
+                _ => return None,
+            }
+        };
+
+        value.push(decoded);
+        offsets.extend(iter::repeat_n(offset, decoded.len_utf8()));
+    }
+
+    offsets.push(literal.len() - 1);
+    Some((value, offsets))
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use pest::iterators::{Pair, Pairs};
+use pest_meta::{ast::Rule as AstRule, optimizer};
+use pest_vm::Vm;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range, Url};
+
+use crate::{
+    analysis::Analysis,
+    helpers::IntoRange,
+    line_index::{LineIndex, PositionEncoding},
+};
+
+/// The marker for a header associating an input file with a grammar, e.g.
+/// `// pest-grammar: ../grammar.pest#rule`.
+const HEADER_MARKER: &str = "pest-grammar:";
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+/// Associates the input files matching a glob with a grammar.
+pub struct SampleInputConfig {
+    /// A glob matching input files, relative to the workspace root.
+    pub glob: String,
+    /// The path to the grammar, relative to the workspace root.
+    pub grammar: String,
+    /// The rule to start parsing from.
+    pub rule: String,
+}
+
+#[derive(Debug, Clone)]
+/// The grammar and rule an input file should be parsed with.
+pub struct SampleAssociation {
+    pub grammar: Url,
+    pub rule: String,
+    /// Whether the first line of the input is a header, which is not part of the input.
+    pub header: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// A pair produced by running a grammar on some input.
+pub struct PairNode {
+    pub rule: String,
+    /// The node tag (`#tag = ...`) attached to the pair, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub span: PairSpan,
+    pub children: Vec<PairNode>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+/// The location of a [PairNode] in the input.
+pub struct PairSpan {
+    /// The byte offset of the start of the pair.
+    pub start: usize,
+    /// The byte offset of the end of the pair.
+    pub end: usize,
+    pub range: Range,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase", tag = "status")]
+/// The result of parsing an input with a grammar.
+pub enum ParseOutcome {
+    Success { pairs: Vec<PairNode> },
+    Failure { message: String, range: Range },
+}
+
+#[derive(Default)]
+/// The parsers of the grammars input files are associated with, each built once, as
+/// optimizing a grammar is costly.
+pub struct SampleParsers(HashMap<Url, Vm>);
+
+impl SampleParsers {
+    fn get(&mut self, grammar: &Url, rules: &[AstRule]) -> &Vm {
+        self.0
+            .entry(grammar.clone())
+            .or_insert_with(|| Vm::new(optimizer::optimize(rules.to_vec())))
+    }
+}
+
+/// Parses `input` starting at `rule`, using the given validated grammar rules.
+///
+/// `rule` must be defined in `rules`, or [pest_vm] will panic. Ranges are in `encoding`.
+pub fn parse_input(
+    rules: &[AstRule],
+    rule: &str,
+    input: &str,
+    encoding: PositionEncoding,
+) -> ParseOutcome {
+    let vm = Vm::new(optimizer::optimize(rules.to_vec()));
+    run(&vm, rule, input, encoding)
+}
+
+fn run(vm: &Vm, rule: &str, input: &str, encoding: PositionEncoding) -> ParseOutcome {
+    let index = LineIndex::new(input, encoding);
+
+    match vm.parse(rule, input) {
+        Ok(pairs) => ParseOutcome::Success {
+            pairs: pair_nodes(pairs, &index),
+        },
+        Err(e) => ParseOutcome::Failure {
+            message: e.variant.message().into_owned(),
+            range: e.location.into_range(&index),
+        },
+    }
+}
+
+impl SampleInputConfig {
+    pub fn association(&self, root: &Path) -> Option<SampleAssociation> {
+        let grammar = Url::from_directory_path(root)
+            .ok()?
+            .join(&self.grammar)
+            .ok()?;
+        Some(SampleAssociation {
+            grammar,
+            rule: self.rule.clone(),
+            header: false,
+        })
+    }
+
+    pub fn matches(&self, root: &Path, path: &Path) -> bool {
+        glob::Pattern::new(&root.join(&self.glob).to_string_lossy())
+            .is_ok_and(|pattern| pattern.matches_path(path))
+    }
+
+    /// Returns the files on disk that match the glob.
+    pub fn files(&self, root: &Path) -> Vec<PathBuf> {
+        let Ok(paths) = glob::glob(&root.join(&self.glob).to_string_lossy()) else {
+            return Vec::new();
+        };
+
+        paths
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect()
+    }
+}
+
+impl SampleAssociation {
+    /// Reads the association from a `pest-grammar: path/to/grammar.pest#rule` header on the
+    /// first line of an input. The path is relative to the input file.
+    pub fn from_header(uri: &Url, text: &str) -> Option<Self> {
+        let (_, header) = text.lines().next()?.split_once(HEADER_MARKER)?;
+        let (path, rule) = header.split_whitespace().next()?.rsplit_once('#')?;
+
+        Some(Self {
+            grammar: uri.join(path).ok()?,
+            rule: rule.to_owned(),
+            header: true,
+        })
+    }
+
+    /// Parses `text` with the associated grammar, returning any errors as diagnostics, with
+    /// positions in `encoding`. The grammar's parser is taken from `parsers`, or added to it.
+    pub fn diagnostics(
+        &self,
+        analysis: Option<&Analysis>,
+        parsers: &mut SampleParsers,
+        text: &str,
+        encoding: PositionEncoding,
+    ) -> Vec<Diagnostic> {
+        let Some(analysis) = analysis else {
+            let message = format!("Grammar {} is not open", self.grammar);
+            return vec![warning(message)];
+        };
+
+        // The grammar is invalid, and reports its own errors.
+        let Some(ast) = analysis.ast.as_ref() else {
+            return Vec::new();
+        };
+
+        if !ast.iter().any(|ast_rule| ast_rule.name == self.rule) {
+            let message = format!("Rule {} is not defined in {}", self.rule, self.grammar);
+            return vec![warning(message)];
+        }
+
+        let (input, line_offset) = match (self.header, text.split_once('\n')) {
+            (false, _) => (text, 0),
+            (true, Some((_, rest))) => (rest, 1),
+            (true, None) => ("", 1),
+        };
+
+        let vm = parsers.get(&self.grammar, ast);
+        match run(vm, &self.rule, input, encoding) {
+            ParseOutcome::Success { .. } => Vec::new(),
+            ParseOutcome::Failure { message, range } => {
+                let range = Range::new(
+                    Position::new(range.start.line + line_offset, range.start.character),
+                    Position::new(range.end.line + line_offset, range.end.character),
+                );
+
+                vec![Diagnostic {
+                    range,
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("Pest Language Server".to_owned()),
+                    message,
+                    ..Default::default()
+                }]
+            }
+        }
+    }
+}
+
+fn warning(message: String) -> Diagnostic {
+    Diagnostic {
+        range: Range::default(),
+        severity: Some(DiagnosticSeverity::WARNING),
+        source: Some("Pest Language Server".to_owned()),
+        message,
+        ..Default::default()
+    }
+}
+
+fn pair_nodes(pairs: Pairs<&str>, index: &LineIndex) -> Vec<PairNode> {
+    pairs.map(|pair| pair_node(pair, index)).collect()
+}
+
+fn pair_node(pair: Pair<&str>, index: &LineIndex) -> PairNode {
+    let span = pair.as_span();
+    let span = PairSpan {
+        start: span.start(),
+        end: span.end(),
+        range: span.into_range(index),
+        text: span.as_str().to_owned(),
+    };
+
+    PairNode {
+        rule: pair.as_rule().to_owned(),
+        tag: pair.as_node_tag().map(str::to_owned),
+        span,
+        children: pair_nodes(pair.into_inner(), index),
+    }
+}
@@ -0,0 +1,222 @@
+use std::{collections::HashSet, str::FromStr};
+
+use pest::{
+    Span,
+    iterators::{Pair, Pairs},
+};
+use pest_meta::parser::Rule;
+use tower_lsp::lsp_types::{
+    Range, SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend,
+};
+
+use crate::{analysis::Analysis, builtins::Builtin, line_index::LineIndex};
+
+const TOKEN_TYPES: [SemanticTokenType; 9] = [
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::STRING,
+    SemanticTokenType::REGEXP,
+    SemanticTokenType::MODIFIER,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::COMMENT,
+];
+
+const TOKEN_MODIFIERS: [SemanticTokenModifier; 4] = [
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::DEFINITION,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+    SemanticTokenModifier::DOCUMENTATION,
+];
+
+/// Builtins that operate on the stack, rather than matching input.
+const STACK_BUILTINS: [&str; 5] = ["PEEK", "PEEK_ALL", "POP", "POP_ALL", "DROP"];
+
+/// Returns the legend for the tokens produced by [semantic_tokens].
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+/// A classified span of the grammar, before it is encoded relative to the previous token.
+struct Token<'a> {
+    span: Span<'a>,
+    token_type: u32,
+    modifiers: u32,
+}
+
+/// Classifies the tokens of a grammar, with positions from an `index` of its text. Rules
+/// count as defined if the grammar or another document of its unit, whose analyses `unit`
+/// holds, defines them. If `range` is given, only tokens on the lines it covers are returned.
+pub fn semantic_tokens<'a>(
+    pairs: Pairs<'a, Rule>,
+    index: &LineIndex,
+    range: Option<Range>,
+    unit: &[&'a Analysis],
+) -> Vec<SemanticToken> {
+    let definitions: HashSet<&str> = pairs
+        .clone()
+        .filter(|pair| pair.as_rule() == Rule::grammar_rule)
+        .filter_map(|pair| pair.into_inner().next())
+        .filter(|pair| pair.as_rule() == Rule::identifier)
+        .map(|pair| pair.as_str())
+        .chain(
+            unit.iter()
+                .flat_map(|analysis| analysis.rules.keys().map(String::as_str)),
+        )
+        .collect();
+
+    let mut tokens = Vec::new();
+    for pair in pairs {
+        classify(pair, &definitions, &mut tokens);
+    }
+
+    encode(tokens, index, range)
+}
+
+fn classify<'a>(pair: Pair<'a, Rule>, definitions: &HashSet<&str>, tokens: &mut Vec<Token<'a>>) {
+    let span = pair.as_span();
+    let mut push = |span, token_type, modifiers: &[SemanticTokenModifier]| {
+        tokens.push(Token {
+            span,
+            token_type: token_type_index(&token_type),
+            modifiers: modifiers_bitset(modifiers),
+        })
+    };
+
+    match pair.as_rule() {
+        Rule::grammar_rule => {
+            let mut inner = pair.into_inner();
+            match inner.next() {
+                Some(identifier) if identifier.as_rule() == Rule::identifier => push(
+                    identifier.as_span(),
+                    SemanticTokenType::FUNCTION,
+                    &[
+                        SemanticTokenModifier::DECLARATION,
+                        SemanticTokenModifier::DEFINITION,
+                    ],
+                ),
+                Some(other) => classify(other, definitions, tokens),
+                None => {}
+            }
+
+            for pair in inner {
+                classify(pair, definitions, tokens);
+            }
+        }
+        Rule::identifier => {
+            let name = pair.as_str();
+            if definitions.contains(name) {
+                push(span, SemanticTokenType::FUNCTION, &[]);
+            } else if STACK_BUILTINS.contains(&name) {
+                push(
+                    span,
+                    SemanticTokenType::MACRO,
+                    &[SemanticTokenModifier::DEFAULT_LIBRARY],
+                );
+            } else if Builtin::from_str(name).is_ok() {
+                push(
+                    span,
+                    SemanticTokenType::FUNCTION,
+                    &[SemanticTokenModifier::DEFAULT_LIBRARY],
+                );
+            } else {
+                push(span, SemanticTokenType::VARIABLE, &[]);
+            }
+        }
+        Rule::_push | Rule::_push_literal | Rule::peek_slice => {
+            let keyword_len = match pair.as_rule() {
+                Rule::_push => "PUSH".len(),
+                Rule::_push_literal => "PUSH_LITERAL".len(),
+                _ => "PEEK".len(),
+            };
+
+            if let Some(keyword) = span.get(..keyword_len) {
+                push(
+                    keyword,
+                    SemanticTokenType::MACRO,
+                    &[SemanticTokenModifier::DEFAULT_LIBRARY],
+                );
+            }
+
+            for pair in pair.into_inner() {
+                classify(pair, definitions, tokens);
+            }
+        }
+        Rule::tag_id => push(span, SemanticTokenType::PROPERTY, &[]),
+        Rule::string | Rule::insensitive_string => push(span, SemanticTokenType::STRING, &[]),
+        Rule::range => push(span, SemanticTokenType::REGEXP, &[]),
+        Rule::silent_modifier
+        | Rule::atomic_modifier
+        | Rule::compound_atomic_modifier
+        | Rule::non_atomic_modifier => push(span, SemanticTokenType::MODIFIER, &[]),
+        Rule::number | Rule::integer => push(span, SemanticTokenType::NUMBER, &[]),
+        Rule::line_doc | Rule::grammar_doc => push(
+            span,
+            SemanticTokenType::COMMENT,
+            &[SemanticTokenModifier::DOCUMENTATION],
+        ),
+        _ => {
+            for pair in pair.into_inner() {
+                classify(pair, definitions, tokens);
+            }
+        }
+    }
+}
+
+/// Encodes tokens relative to each other, splitting tokens that span multiple lines.
+fn encode(tokens: Vec<Token>, index: &LineIndex, range: Option<Range>) -> Vec<SemanticToken> {
+    let mut encoded = Vec::with_capacity(tokens.len());
+    let (mut previous_line, mut previous_start) = (0, 0);
+
+    for token in tokens {
+        let position = index.position(token.span.start());
+        for (offset, segment) in token.span.as_str().split('\n').enumerate() {
+            let line = position.line + offset as u32;
+            let start = if offset == 0 { position.character } else { 0 };
+            let length = index.encoding().columns(segment.trim_end_matches('\r'));
+
+            if length == 0
+                || range.is_some_and(|range| line < range.start.line || line > range.end.line)
+            {
+                continue;
+            }
+
+            let delta_line = line - previous_line;
+            let delta_start = if delta_line == 0 {
+                start - previous_start
+            } else {
+                start
+            };
+
+            encoded.push(SemanticToken {
+                delta_line,
+                delta_start,
+                length,
+                token_type: token.token_type,
+                token_modifiers_bitset: token.modifiers,
+            });
+            (previous_line, previous_start) = (line, start);
+        }
+    }
+
+    encoded
+}
+
+fn token_type_index(token_type: &SemanticTokenType) -> u32 {
+    TOKEN_TYPES
+        .iter()
+        .position(|t| t == token_type)
+        .expect("token type should be in the legend") as u32
+}
+
+fn modifiers_bitset(modifiers: &[SemanticTokenModifier]) -> u32 {
+    TOKEN_MODIFIERS
+        .iter()
+        .enumerate()
+        .filter(|(_, modifier)| modifiers.contains(modifier))
+        .fold(0, |bitset, (index, _)| bitset | 1 << index)
+}
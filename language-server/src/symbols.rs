@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use pest::iterators::{Pair, Pairs};
+use pest_meta::parser::Rule;
+use tower_lsp::lsp_types::{DocumentSymbol, Range, SymbolKind};
+
+use crate::{analysis::Analysis, completion::Modifier, helpers::IntoRange, line_index::LineIndex};
+
+/// The length at which expressions are cut off in symbol details.
+const MAX_DETAIL_LENGTH: usize = 40;
+
+/// Returns the outline of a grammar: its `//!` documentation, followed by its rules in the
+/// order they are defined, with the tags in their expressions as children. Tags are only
+/// found if the grammar's `pairs` are given, i.e. if it currently parses, with an `index` of
+/// its text.
+// `DocumentSymbol::deprecated` has to be set, even though it is deprecated.
+#[allow(deprecated)]
+pub fn document_symbols(
+    analysis: &Analysis,
+    pairs: Option<(Pairs<Rule>, &LineIndex)>,
+) -> Vec<DocumentSymbol> {
+    let mut tags: HashMap<String, Vec<DocumentSymbol>> = HashMap::new();
+    for (pair, index) in pairs
+        .into_iter()
+        .flat_map(|(pairs, index)| pairs.map(move |pair| (pair, index)))
+        .filter(|(pair, _)| pair.as_rule() == Rule::grammar_rule)
+    {
+        let mut inner = pair.into_inner();
+        let Some(identifier) = inner.next().filter(|p| p.as_rule() == Rule::identifier) else {
+            continue;
+        };
+
+        tags.insert(
+            identifier.as_str().to_owned(),
+            inner.flat_map(|pair| tag_symbols(pair, index)).collect(),
+        );
+    }
+
+    let grammar_doc = analysis
+        .doc
+        .as_ref()
+        .zip(analysis.doc_location)
+        .map(|(doc, range)| DocumentSymbol {
+            name: doc
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .unwrap_or("Grammar documentation")
+                .to_owned(),
+            detail: None,
+            kind: SymbolKind::MODULE,
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range: range,
+            children: None,
+        });
+
+    let mut rules: Vec<_> = analysis.rules.iter().collect();
+    rules.sort_by_key(|(_, ra)| ra.definition_location.start);
+
+    let rules = rules.into_iter().map(|(name, ra)| {
+        let modifier = Modifier::of(ra.rule_type).map_or("", |modifier| modifier.symbol);
+        // The rule's documentation is part of it, so the outline selects it too.
+        let start = ra
+            .doc_location
+            .map_or(ra.definition_location.start, |doc| doc.start);
+
+        DocumentSymbol {
+            name: name.to_owned(),
+            detail: Some(format!("{modifier}{{ {} }}", abbreviate(&ra.expression))),
+            kind: SymbolKind::FIELD,
+            tags: None,
+            deprecated: None,
+            range: Range::new(start, ra.definition_location.end),
+            selection_range: ra.identifier_location,
+            children: tags.remove(name).filter(|tags| !tags.is_empty()),
+        }
+    });
+
+    grammar_doc.into_iter().chain(rules).collect()
+}
+
+/// Returns the symbols of the outermost tags in an expression, with the tags nested in the
+/// expressions they name as children.
+#[allow(deprecated)]
+fn tag_symbols(pair: Pair<Rule>, index: &LineIndex) -> Vec<DocumentSymbol> {
+    if pair.as_rule() == Rule::term
+        && let Some(tag) = pair
+            .clone()
+            .into_inner()
+            .next()
+            .filter(|p| p.as_rule() == Rule::tag_id)
+    {
+        let children: Vec<_> = pair
+            .clone()
+            .into_inner()
+            .flat_map(|pair| tag_symbols(pair, index))
+            .collect();
+        let expression = pair
+            .as_str()
+            .split_once('=')
+            .map_or("", |(_, expression)| expression);
+
+        return vec![DocumentSymbol {
+            name: tag.as_str().to_owned(),
+            detail: Some(abbreviate(expression)),
+            kind: SymbolKind::PROPERTY,
+            tags: None,
+            deprecated: None,
+            range: pair.as_span().into_range(index),
+            selection_range: tag.as_span().into_range(index),
+            children: (!children.is_empty()).then_some(children),
+        }];
+    }
+
+    pair.into_inner()
+        .flat_map(|pair| tag_symbols(pair, index))
+        .collect()
+}
+
+/// Shortens an expression to a single line for a symbol detail.
+fn abbreviate(expression: &str) -> String {
+    let expression = expression.split_whitespace().collect::<Vec<_>>().join(" ");
+    match expression.char_indices().nth(MAX_DETAIL_LENGTH) {
+        Some((index, _)) => format!("{}…", &expression[..index]),
+        None => expression,
+    }
+}
@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use pest::error::{Error, InputLocation};
+use pest_meta::parser::{self, Rule};
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, Location, Range, TextDocumentItem, Url,
+};
+
+use crate::{
+    analysis::Analysis,
+    cycles::Cycles,
+    helpers::{IntoDiagnostics, error_diagnostic, validate_pairs},
+    line_index::{LineIndex, PositionEncoding},
+    lsp::Config,
+};
+
+#[derive(Debug)]
+/// The result of analysing one document of a grammar unit.
+pub struct DocumentAnalysis {
+    pub uri: Url,
+    /// [None] if the document could not be parsed.
+    pub analysis: Option<Analysis>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Resolves the files of a configured grammar unit, relative to a workspace root.
+pub fn resolve_unit(files: &[String], root: &Path) -> Vec<Url> {
+    let Ok(root) = Url::from_directory_path(root) else {
+        return Vec::new();
+    };
+
+    files
+        .iter()
+        .filter_map(|file| root.join(file).ok())
+        .collect()
+}
+
+/// Analyses a grammar unit: a set of grammars which pest_derive concatenates into a single
+/// parser, e.g. through multiple `#[grammar]` attributes. Rules can be referenced across the
+/// documents of a unit, so they are validated together, and a rule is only unused if no
+/// document references it.
+///
+/// A document that is not part of any unit is a unit of its own. `is_used` reports whether a
+/// rule is used outside of the unit, e.g. from Rust code. Positions are in `encoding`.
+pub fn analyse_unit(
+    config: &Config,
+    documents: &[&TextDocumentItem],
+    encoding: PositionEncoding,
+    capacity: impl Fn(&Url) -> Option<usize>,
+    is_used: impl Fn(&str) -> bool,
+) -> Vec<DocumentAnalysis> {
+    let indexes: Vec<_> = documents
+        .iter()
+        .map(|document| LineIndex::new(&document.text, encoding))
+        .collect();
+
+    let mut results: Vec<_> = documents
+        .iter()
+        .zip(&indexes)
+        .map(
+            |(document, index)| match parser::parse(Rule::grammar_rules, &document.text) {
+                Ok(pairs) => DocumentAnalysis {
+                    uri: document.uri.clone(),
+                    analysis: Some(Analysis::new(pairs, index, capacity(&document.uri))),
+                    diagnostics: Vec::new(),
+                },
+                Err(error) => DocumentAnalysis {
+                    uri: document.uri.clone(),
+                    analysis: None,
+                    diagnostics: vec![error].into_diagnostics(index),
+                },
+            },
+        )
+        .collect();
+
+    let unused_diagnostics = unused_diagnostics(config, &results, is_used);
+    for (index, diagnostic) in unused_diagnostics {
+        results[index].diagnostics.push(diagnostic);
+    }
+
+    // The unit cannot be validated until every document parses.
+    if results.iter().any(|result| result.analysis.is_none()) {
+        return results;
+    }
+
+    // Documents are separated by a newline, so a trailing comment cannot swallow the first
+    // line of the next document.
+    let mut text = String::new();
+    let mut offsets = Vec::with_capacity(documents.len());
+    for document in documents {
+        offsets.push(text.len());
+        text.push_str(&document.text);
+        text.push('\n');
+    }
+
+    let pairs = match parser::parse(Rule::grammar_rules, &text) {
+        Ok(pairs) => pairs,
+        Err(error) => {
+            let (document, range) = locate_error(&indexes, &offsets, &error);
+            results[document].diagnostics.push(Diagnostic {
+                range,
+                ..error_diagnostic(&error, &indexes[document])
+            });
+            return results;
+        }
+    };
+
+    match validate_pairs(pairs.clone()) {
+        Ok(ast) => {
+            for analysis in results.iter_mut().filter_map(|r| r.analysis.as_mut()) {
+                analysis.ast = Some(ast.clone());
+            }
+        }
+        Err(errors) => {
+            let cycles = Cycles::new(pairs);
+            let locate_rule = |name: &str| {
+                results.iter().find_map(|result| {
+                    let ra = result.analysis.as_ref()?.rules.get(name)?;
+                    Some(Location::new(result.uri.clone(), ra.identifier_location))
+                })
+            };
+
+            let diagnostics: Vec<_> = errors
+                .iter()
+                .map(|error| {
+                    let (document, range) = locate_error(&indexes, &offsets, error);
+                    let diagnostic = Diagnostic {
+                        range,
+                        related_information: cycles.related_information(error, locate_rule),
+                        ..error_diagnostic(error, &indexes[document])
+                    };
+                    (document, diagnostic)
+                })
+                .collect();
+
+            for (index, diagnostic) in diagnostics {
+                results[index].diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    results
+}
+
+/// Warns about the rules of a unit that no document references, by document index.
+fn unused_diagnostics(
+    config: &Config,
+    results: &[DocumentAnalysis],
+    is_used: impl Fn(&str) -> bool,
+) -> Vec<(usize, Diagnostic)> {
+    let analyses = || results.iter().filter_map(|result| result.analysis.as_ref());
+    let is_referenced = |name: &str| {
+        is_used(name) || analyses().any(|analysis| analysis.identifiers.contains_key(name))
+    };
+
+    let mut diagnostics: Vec<_> = results
+        .iter()
+        .enumerate()
+        .filter_map(|(index, result)| Some((index, result.analysis.as_ref()?)))
+        .flat_map(|(index, analysis)| {
+            analysis
+                .unused_rules(is_referenced)
+                .map(move |unused| (index, unused))
+        })
+        .filter(|(_, (rule_name, _))| {
+            !config
+                .always_used_rule_names
+                .iter()
+                .map(String::as_str)
+                .chain(["WHITESPACE", "COMMENT"])
+                .any(|name| name == *rule_name)
+        })
+        .map(|(index, (rule_name, range))| {
+            let diagnostic = Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                source: Some("Pest Language Server".to_owned()),
+                message: format!("Rule {} is unused", rule_name),
+                ..Default::default()
+            };
+            (index, diagnostic)
+        })
+        .collect();
+
+    if config.always_used_rule_names.is_empty() && diagnostics.len() == 1 {
+        diagnostics.clear();
+    }
+
+    diagnostics
+}
+
+/// Maps the location of an error in the concatenated text of a unit back to the document it
+/// occurred in, returning the index of the document and the range within it.
+fn locate_error(indexes: &[LineIndex], offsets: &[usize], error: &Error<Rule>) -> (usize, Range) {
+    let (start, end) = match error.location {
+        InputLocation::Pos(pos) => (pos, pos),
+        InputLocation::Span(span) => span,
+    };
+
+    let document = offsets.partition_point(|&offset| offset <= start) - 1;
+    let start = start - offsets[document];
+    let end = (end - offsets[document]).max(start);
+    (document, indexes[document].range(start..end))
+}
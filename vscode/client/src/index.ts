@@ -1,6 +1,7 @@
 import { findServer } from "./server";
 import {
 	commands,
+	ConfigurationTarget,
 	ExtensionContext,
 	RelativePattern,
 	TextDocument,
@@ -52,6 +53,10 @@ async function startClientsForFolder(
 		.getConfiguration("pestIdeTools")
 		.get("customArgs") as string[];
 
+	const sampleInputs = workspace
+		.getConfiguration("pestIdeTools")
+		.get("sampleInputs") as { glob: string }[];
+
 	const root = folder.uri;
 	const pestFiles: Set<string> = new Set();
 
@@ -81,6 +86,9 @@ async function startClientsForFolder(
 		{
 			documentSelector: [
 				{ language: "pest", pattern: `${root.fsPath}/**/*.pest` },
+				...sampleInputs.map(({ glob }) => ({
+					pattern: `${root.fsPath}/${glob}`,
+				})),
 			],
 			synchronize: { fileEvents: deleteWatcher },
 			diagnosticCollectionName: extensionName,
@@ -137,7 +145,10 @@ export async function activate(context: ExtensionContext): Promise<void> {
 
 	context.subscriptions.push(
 		workspace.onDidChangeConfiguration(async e => {
-			if (e.affectsConfiguration("pestIdeTools.serverPath")) {
+			if (
+				e.affectsConfiguration("pestIdeTools.serverPath") ||
+				e.affectsConfiguration("pestIdeTools.sampleInputs")
+			) {
 				for (const client of clients.values()) {
 					const folder = client.clientOptions.workspaceFolder;
 					await stopClient(client);
@@ -159,6 +170,22 @@ export async function activate(context: ExtensionContext): Promise<void> {
 		}),
 	);
 
+	commands.registerCommand(
+		"pestIdeTools.addAlwaysUsedRuleName",
+		async (name: string) => {
+			const config = workspace.getConfiguration("pestIdeTools");
+			const names = config.get("alwaysUsedRuleNames") as string[];
+
+			if (!names.includes(name)) {
+				await config.update(
+					"alwaysUsedRuleNames",
+					[...names, name],
+					ConfigurationTarget.Workspace,
+				);
+			}
+		},
+	);
+
 	commands.registerCommand("pestIdeTools.restartServer", async () => {
 		const currentFolder = workspace.workspaceFolders?.[0].uri.toString();
 